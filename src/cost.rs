@@ -1,39 +1,43 @@
 use std::ops::Add;
-use std::fmt::Display;
 use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Cost<W: Ord + Clone + Add<Output=W> + Display> {
+use crate::metric::Metric;
+
+/// The weight of a path. Whatever the algebra, `Zero` is a node's distance to itself and
+/// `Infinity` means there's no path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Cost<W: Metric> {
     Zero,
     Value(W),
     Infinity
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> PartialOrd for Cost<W> {
+impl<W: Metric> PartialOrd for Cost<W> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<W: Metric> Ord for Cost<W> {
+    fn cmp(&self, other: &Self) -> Ordering {
         if self == other {
-            Some(Ordering::Equal)
+            Ordering::Equal
         } else {
             match self {
-                Cost::Infinity => Some(Ordering::Greater),
-                Cost::Zero => Some(Ordering::Less),
+                Cost::Infinity => Ordering::Greater,
+                Cost::Zero => Ordering::Less,
                 Cost::Value(w) => match other {
-                    Cost::Infinity => Some(Ordering::Less),
-                    Cost::Zero => Some(Ordering::Greater),
-                    Cost::Value(w2) => Some(w.cmp(w2))
+                    Cost::Infinity => Ordering::Less,
+                    Cost::Zero => Ordering::Greater,
+                    Cost::Value(w2) => w.prefer(w2)
                 }
             }
         }
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> Ord for Cost<W> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
-
-impl<W: Ord + Clone + Add<Output=W> + Display> Add for Cost<W> {
+impl<W: Metric> Add for Cost<W> {
     type Output = Cost<W>;
 
     fn add(self, rhs: Self) -> Self::Output {
@@ -43,43 +47,170 @@ impl<W: Ord + Clone + Add<Output=W> + Display> Add for Cost<W> {
             Cost::Value(w) => match rhs {
                 Cost::Infinity => Cost::Infinity,
                 Cost::Zero => self,
-                Cost::Value(w2) => Cost::Value(w.to_owned() + w2)
+                Cost::Value(w2) => Cost::Value(w.combine(&w2))
             }
         }
     }
 }
 
 #[derive(Debug, Clone)]
-pub enum DVValue<W: Ord + Clone + Add<Output=W> + Display> {
+pub enum DVValue<W: Metric> {
     Infinity,
     Distance(W, usize),
     DirectDistance(W),
     SameNode,
+    /// A path vector route, with every node on the way starting at the next hop
+    Path(W, Vec<usize>),
+    /// A DSDV route: the cost, the next hop and the destination's sequence number. Odd
+    /// numbers come with an infinite cost after a link broke, a node's own entry is zero
+    Dsdv(Cost<W>, usize, u32),
+    /// An equal-cost multipath route, with every next hop that ties for the cost, sorted
+    Multipath(W, Vec<usize>),
+}
+
+impl<W: Metric> DVValue<W> {
+    /// The neighbor packets for `target` are sent to, if there's a route at all.
+    pub fn next_hop(&self, target: usize) -> Option<usize> {
+        match self {
+            DVValue::Distance(_, via) => Some(*via),
+            DVValue::DirectDistance(_) => Some(target),
+            DVValue::Path(_, path) | DVValue::Multipath(_, path) => path.first().copied(),
+            DVValue::Dsdv(Cost::Value(_), via, _) => Some(*via),
+            DVValue::Infinity | DVValue::SameNode | DVValue::Dsdv(..) => None
+        }
+    }
+
+    /// Every neighbor packets for `target` can be sent to, more than one for multipath routes.
+    pub fn next_hops(&self, target: usize) -> Vec<usize> {
+        match self {
+            DVValue::Multipath(_, hops) => hops.clone(),
+            _ => self.next_hop(target).into_iter().collect()
+        }
+    }
+
+    /// The destination sequence number of a DSDV route, 0 for anything else.
+    pub fn sequence(&self) -> u32 {
+        match self {
+            DVValue::Dsdv(_, _, sequence) => *sequence,
+            _ => 0
+        }
+    }
+
+    /// What's left of the route when the link to its next hop breaks. A DSDV route keeps
+    /// its next hop and moves to the odd sequence number after its own.
+    pub fn broken(&self) -> DVValue<W> {
+        match self {
+            DVValue::Dsdv(_, via, sequence) => DVValue::Dsdv(Cost::Infinity, *via, sequence | 1),
+            _ => DVValue::Infinity
+        }
+    }
+
+    /// The nodes a route to `target` goes through, starting at the next hop. Routes that
+    /// only know their next hop are assumed to go straight on to `target`, multipath routes
+    /// through the first one.
+    pub fn path(&self, target: usize) -> Option<Vec<usize>> {
+        match self {
+            DVValue::Path(_, path) => Some(path.clone()),
+            DVValue::Distance(_, via) if *via != target => Some(vec!(*via, target)),
+            DVValue::Distance(..) | DVValue::DirectDistance(_) => Some(vec!(target)),
+            DVValue::Multipath(_, hops) if hops[0] != target => Some(vec!(hops[0], target)),
+            DVValue::Multipath(..) => Some(vec!(target)),
+            DVValue::Dsdv(Cost::Value(_), via, _) if *via != target => Some(vec!(*via, target)),
+            DVValue::Dsdv(Cost::Value(_), ..) => Some(vec!(target)),
+            DVValue::SameNode | DVValue::Dsdv(Cost::Zero, ..) => Some(Vec::new()),
+            DVValue::Infinity | DVValue::Dsdv(Cost::Infinity, ..) => None
+        }
+    }
+
+    /// Equality only compares costs, this also wants the same next hops towards `target`,
+    /// the same path for path vector routes and the same sequence number for DSDV.
+    pub fn same_route(&self, other: &Self, target: usize) -> bool {
+        match (self, other) {
+            (DVValue::Path(..), _) | (_, DVValue::Path(..)) => self == other && self.path(target) == other.path(target),
+            (DVValue::Dsdv(..), _) | (_, DVValue::Dsdv(..)) =>
+                self == other && self.next_hop(target) == other.next_hop(target) && self.sequence() == other.sequence(),
+            _ => self == other && self.next_hops(target) == other.next_hops(target)
+        }
+    }
+}
+
+/// Whether two tables have the same cost and next hop for every destination.
+pub fn same_routes<W: Metric>(a: &[DVValue<W>], b: &[DVValue<W>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).enumerate().all(|(target, (x, y))| x.same_route(y, target))
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> Into<Cost<W>> for DVValue<W> {
-    fn into(self) -> Cost<W> {
+impl<W: Metric> DVValue<W> {
+    /// Hashes the whole route. Unlike equality this includes the next hop, two states
+    /// that only route differently aren't the same.
+    pub fn hash_route<H: Hasher>(&self, state: &mut H) {
         match self {
+            DVValue::Infinity => 0u8.hash(state),
+            DVValue::Distance(w, via) => {
+                1u8.hash(state);
+                w.hash(state);
+                via.hash(state);
+            }
+            DVValue::DirectDistance(w) => {
+                2u8.hash(state);
+                w.hash(state);
+            }
+            DVValue::SameNode => 3u8.hash(state),
+            DVValue::Path(w, path) => {
+                4u8.hash(state);
+                w.hash(state);
+                path.hash(state);
+            }
+            DVValue::Dsdv(cost, via, sequence) => {
+                5u8.hash(state);
+                match cost {
+                    Cost::Zero => 0u8.hash(state),
+                    Cost::Value(w) => {
+                        1u8.hash(state);
+                        w.hash(state);
+                    }
+                    Cost::Infinity => 2u8.hash(state)
+                }
+                via.hash(state);
+                sequence.hash(state);
+            }
+            DVValue::Multipath(w, hops) => {
+                6u8.hash(state);
+                w.hash(state);
+                hops.hash(state);
+            }
+        }
+    }
+}
+
+impl<W: Metric> From<DVValue<W>> for Cost<W> {
+    fn from(value: DVValue<W>) -> Cost<W> {
+        match value {
             DVValue::Infinity => Cost::Infinity,
             DVValue::SameNode => Cost::Zero,
             DVValue::DirectDistance(w) => Cost::Value(w),
-            DVValue::Distance(w,_) => Cost::Value(w)
+            DVValue::Distance(w,_) => Cost::Value(w),
+            DVValue::Path(w, _) => Cost::Value(w),
+            DVValue::Dsdv(cost, _, _) => cost,
+            DVValue::Multipath(w, _) => Cost::Value(w)
         }
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> Into<Cost<W>> for &DVValue<W> {
-    fn into(self) -> Cost<W> {
-        match self {
+impl<W: Metric> From<&DVValue<W>> for Cost<W> {
+    fn from(value: &DVValue<W>) -> Cost<W> {
+        match value {
             DVValue::Infinity => Cost::Infinity,
             DVValue::SameNode => Cost::Zero,
             DVValue::DirectDistance(w) => Cost::Value(w.to_owned()),
-            DVValue::Distance(w,_) => Cost::Value(w.to_owned())
+            DVValue::Distance(w,_) => Cost::Value(w.to_owned()),
+            DVValue::Path(w, _) => Cost::Value(w.to_owned()),
+            DVValue::Dsdv(cost, _, _) => cost.to_owned(),
+            DVValue::Multipath(w, _) => Cost::Value(w.to_owned())
         }
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> PartialEq for DVValue<W> {
+impl<W: Metric> PartialEq for DVValue<W> {
     fn eq(&self, other: &Self) -> bool {
         let cost_self:Cost<W> = self.into();
         let cost_other:Cost<W> = other.into();
@@ -88,7 +219,19 @@ impl<W: Ord + Clone + Add<Output=W> + Display> PartialEq for DVValue<W> {
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> Cost<W> {
+impl<W: Metric> Cost<W> {
+    /// Collapses anything that isn't better than `max_metric` to infinity.
+    pub fn capped(self, max_metric: Option<&W>) -> Cost<W> {
+        match (&self, max_metric) {
+            (Cost::Value(w), Some(max)) if w.prefer(max) != Ordering::Less => Cost::Infinity,
+            _ => self
+        }
+    }
+
+    pub fn add_capped(self, rhs: Self, max_metric: Option<&W>) -> Cost<W> {
+        (self + rhs).capped(max_metric)
+    }
+
     pub fn to_dv_value(&self, through: usize, direct: bool) -> DVValue<W> {
         match self {
             Cost::Infinity => DVValue::Infinity,
@@ -101,4 +244,46 @@ impl<W: Ord + Clone + Add<Output=W> + Display> Cost<W> {
                 }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_route_wants_the_same_next_hop() {
+        let through_1: DVValue<u32> = DVValue::Distance(3, 1);
+
+        assert!(through_1.same_route(&DVValue::Distance(3, 1), 0));
+        assert!(!through_1.same_route(&DVValue::Distance(3, 2), 0));
+        assert!(!through_1.same_route(&DVValue::Distance(4, 1), 0));
+        // A direct route has the destination as its next hop
+        assert!(DVValue::Distance(3, 0).same_route(&DVValue::DirectDistance(3), 0));
+    }
+
+    #[test]
+    fn same_route_compares_paths_sequences_and_multipath_hops() {
+        let path: DVValue<u32> = DVValue::Path(3, vec!(1, 2, 0));
+        assert!(path.same_route(&DVValue::Path(3, vec!(1, 2, 0)), 0));
+        assert!(!path.same_route(&DVValue::Path(3, vec!(1, 3, 0)), 0));
+
+        let dsdv: DVValue<u32> = DVValue::Dsdv(Cost::Value(3), 1, 4);
+        assert!(dsdv.same_route(&DVValue::Dsdv(Cost::Value(3), 1, 4), 0));
+        assert!(!dsdv.same_route(&DVValue::Dsdv(Cost::Value(3), 1, 6), 0));
+        assert!(!dsdv.same_route(&DVValue::Dsdv(Cost::Value(3), 2, 4), 0));
+
+        let multipath: DVValue<u32> = DVValue::Multipath(3, vec!(1, 2));
+        assert!(multipath.same_route(&DVValue::Multipath(3, vec!(1, 2)), 0));
+        assert!(!multipath.same_route(&DVValue::Multipath(3, vec!(1)), 0));
+    }
+
+    #[test]
+    fn same_routes_compares_every_destination() {
+        let table: Vec<DVValue<u32>> = vec!(DVValue::SameNode, DVValue::Distance(2, 2), DVValue::DirectDistance(1));
+        let rerouted: Vec<DVValue<u32>> = vec!(DVValue::SameNode, DVValue::Distance(2, 3), DVValue::DirectDistance(1));
+
+        assert!(same_routes(&table, &table.clone()));
+        assert!(!same_routes(&table, &rerouted));
+        assert!(!same_routes(&table, &table[..2]));
+    }
+}
@@ -1,550 +1,496 @@
 mod repr;
 mod cost;
+mod metric;
+mod options;
+mod random;
+mod scenario;
+mod world;
 
-use std::collections::{HashMap, HashSet, BTreeMap};
+use std::env;
 use std::error::Error;
-use std::fmt::Display;
-use std::io::Write;
-use std::ops::Add;
-use crate::cost::DVValue;
-use crate::repr::{HtmlFormula, DistanceCalculationLine, HtmlFiles};
-use std::path::Path;
-
-#[derive(Debug, Clone)]
-struct Neighbor<W: Ord + Clone + Add<Output=W> + Display> {
-    index: usize,
-    direct_cost: W,
-    dv: Vec<DVValue<W>>
-}
-
-#[derive(Debug, Clone)]
-struct Node<W: Ord + Clone + Add<Output=W> + Display> {
-    name: String,
-    dv: Vec<DVValue<W>>,
-    neighbors: Vec<Neighbor<W>>,
-    index: usize,
-    has_updates: bool
-}
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::metric::{Bandwidth, HopBounded, Metric, Reliability};
+use crate::options::{Algebra, Engine, HorizonMode, Protocol, TieBreak, UpdateOrder};
+use crate::repr::{HtmlFiles, OutputFormat, find_chrome, render_pdf};
+use crate::scenario::{PacketSpec, Scenario};
+use crate::world::events::check_events;
+use crate::world::{Convergence, World};
+
+const USAGE: &str = "\
+usage: dvr_sim [options] <command>
+
+commands:
+    run <scenario>      run a scenario file, or a scenario by name from the scenarios directory
+    trace <scenario> <from> <to>
+                        run a scenario and follow a packet from one node to another
+    compare <scenario>  run a scenario with distance vector, path vector, DSDV, DUAL and
+                        link state, and compare the rounds and messages every run took
+    list                list the scenarios in the scenarios directory
+    render              print the html pages in the output directory to pdf
+
+options:
+    -o, --output <dir>      output directory (default: output)
+    -p, --prefix <prefix>   file prefix for the pages (default: the scenario name)
+    -f, --format <formats>  comma separated output formats, html and/or pdf (default: html)
+    -s, --scenarios <dir>   scenarios directory (default: scenarios)
+        --chrome <path>     chrome binary used for pdf output (default: google-chrome
+                            or chromium on PATH)
+        --protocol <protocol>
+                            override the scenario's protocol: distance_vector, path_vector,
+                            dsdv, dual or link_state
+        --engine <engine>   override the scenario's engine: lockstep or events
+        --update-order <order>
+                            override the scenario's update order: simultaneous,
+                            sequential or random
+        --order <nodes>     comma separated nodes that go first in sequential order
+        --horizon <mode>    override the scenario's horizon: plain, split_horizon
+                            or poison_reverse
+        --algebra <algebra> override how weights combine and which path is best:
+                            shortest_path, widest_path, most_reliable or hop_bounded
+        --max-metric <n>    override the scenario's maximum metric, distances at or
+                            above it are unreachable, or paths that aren't better
+        --hold-down <n>     override the scenario's hold-down rounds, 0 turns it off
+        --max-generations <n>
+                            give up after this many generations of a run (default: 1000)
+        --no-verify         don't compare converged tables with the shortest paths
+        --tie-break <policy>
+                            override how ties between neighbors are broken: lowest_index,
+                            lowest_name, keep_current or random
+        --ecmp              keep every next hop that ties for the shortest distance,
+                            distance vector only
+        --at <t>            when trace sends its packet (default: once the run settles)
+        --seed <n>          seed for lossy links, random update order and random ties, the same seed
+                            replays the same run (default: the scenario's seed, or a new
+                            one that gets printed)
+    -h, --help              show this message";
 
 #[derive(Debug)]
-enum Operation<W: Ord + Clone + Add<Output=W> + Display> {
-    ChangeWeight(usize, usize, W)
+enum Command {
+    Run(String),
+    Trace(String, String, String),
+    Compare(String),
+    List,
+    Render,
+    Help,
 }
 
 #[derive(Debug)]
-struct World<W: Ord + Clone + Add<Output=W> + Display> {
-    nodes: Vec<Node<W>>,
-    generation: u32
+struct Args {
+    command: Command,
+    output: PathBuf,
+    prefix: Option<String>,
+    formats: Vec<OutputFormat>,
+    scenarios: PathBuf,
+    chrome: Option<String>,
+    protocol: Option<Protocol>,
+    engine: Option<Engine>,
+    update_order: Option<UpdateOrder>,
+    order: Option<Vec<String>>,
+    horizon: Option<HorizonMode>,
+    algebra: Option<Algebra>,
+    max_metric: Option<u32>,
+    hold_down: Option<u32>,
+    max_generations: Option<u32>,
+    seed: Option<u64>,
+    verify: bool,
+    ecmp: bool,
+    tie_break: Option<TieBreak>,
+    at: Option<u32>,
 }
 
-#[derive(Debug)]
-enum NewState<W: Ord + Clone + Add<Output=W> + Display> {
-    Changed(World<W>),
-    NotChanged,
-}
+fn parse_formats(value: &str) -> Result<Vec<OutputFormat>, Box<dyn Error>> {
+    let mut formats = Vec::new();
 
-fn modify_dv<W: Ord + Clone + Add<Output=W> + Display>(
-    original: &Vec<DVValue<W>>,
-    node_b: usize,
-    new_w: W,
-) -> Vec<DVValue<W>> {
-    let mut new_dv = Vec::new();
-    let mut index: usize = 0;
-
-    for v in original {
-        if index == node_b {
-            new_dv.push(DVValue::Distance(new_w.to_owned(), node_b));
-        } else {
-            new_dv.push(v.clone());
-        }
-        index += 1;
+    for part in value.split(',') {
+        formats.push(part.trim().parse::<OutputFormat>()?);
     }
 
-    new_dv
+    Ok(formats)
 }
 
-
-impl<W: Ord + Clone + Add<Output=W> + Display> World<W> {
-    pub fn new(node_names: Vec<&str>) -> World<W> {
-        let size = node_names.len();
-
-        let mut index: usize = 0;
-        let mut nodes: Vec<Node<W>> = Vec::with_capacity(size);
-
-        for name in node_names {
-            let mut dv_vector: Vec<DVValue<W>> = Vec::with_capacity(size);
-
-            for _ in 0 .. size {
-                dv_vector.push(DVValue::Infinity)
-            }
-
-            nodes.push(Node{
-                name: name.to_owned(),
-                dv: dv_vector,
-                neighbors: Vec::new(),
-                index,
-                has_updates: false
-            });
-
-            index += 1;
-        }
-
-        World { nodes, generation: 0 }
-    }
-
-    fn find_node(&self, name: &str) -> Option<&Node<W>> {
-        self.nodes
-            .iter()
-            .find(|n| n.name == name)
-    }
-
-    fn node_names(&self) -> BTreeMap<usize, String> {
-        let mut names:BTreeMap<usize, String> = BTreeMap::new();
-
-        for sub_node in &self.nodes {
-            names.insert(sub_node.index, sub_node.name.to_owned());
+fn parse_args(raw: Vec<String>) -> Result<Args, Box<dyn Error>> {
+    let mut output = PathBuf::from("output");
+    let mut prefix = None;
+    let mut formats = vec!(OutputFormat::Html);
+    let mut scenarios = PathBuf::from("scenarios");
+    let mut chrome = None;
+    let mut protocol = None;
+    let mut engine = None;
+    let mut update_order = None;
+    let mut order = None;
+    let mut horizon = None;
+    let mut algebra = None;
+    let mut max_metric = None;
+    let mut hold_down = None;
+    let mut max_generations = None;
+    let mut seed = None;
+    let mut verify = true;
+    let mut ecmp = false;
+    let mut tie_break = None;
+    let mut at = None;
+    let mut positional: Vec<String> = Vec::new();
+
+    let mut iter = raw.into_iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", name));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = PathBuf::from(value(&arg)?),
+            "-p" | "--prefix" => prefix = Some(value(&arg)?),
+            "-f" | "--format" => formats = parse_formats(value(&arg)?.as_str())?,
+            "-s" | "--scenarios" => scenarios = PathBuf::from(value(&arg)?),
+            "--chrome" => chrome = Some(value(&arg)?),
+            "--protocol" => protocol = Some(value(&arg)?.parse::<Protocol>()?),
+            "--engine" => engine = Some(value(&arg)?.parse::<Engine>()?),
+            "--update-order" => update_order = Some(value(&arg)?.parse::<UpdateOrder>()?),
+            "--order" => order = Some(value(&arg)?.split(',').map(|s| s.trim().to_owned()).collect()),
+            "--horizon" => horizon = Some(value(&arg)?.parse::<HorizonMode>()?),
+            "--algebra" => algebra = Some(value(&arg)?.parse::<Algebra>()?),
+            "--max-metric" => max_metric = Some(value(&arg)?.parse::<u32>()?),
+            "--hold-down" => hold_down = Some(value(&arg)?.parse::<u32>()?),
+            "--max-generations" => max_generations = Some(value(&arg)?.parse::<u32>()?),
+            "--seed" => seed = Some(value(&arg)?.parse::<u64>()?),
+            "--no-verify" => verify = false,
+            "--ecmp" => ecmp = true,
+            "--tie-break" => tie_break = Some(value(&arg)?.parse::<TieBreak>()?),
+            "--at" => at = Some(value(&arg)?.parse::<u32>()?),
+            "-h" | "--help" => positional = vec!(String::from("help")),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg).into()),
+            _ => positional.push(arg),
         }
-
-        names
     }
 
-    fn add_interface(&self, node_a: &str, node_b: &str, weight: W) -> Result<Operation<W>, Box<dyn Error>> {
-        Ok(Operation::ChangeWeight(
-            self.find_node(node_a)
-                .map(|n| n.index)
-                .ok_or("can't find node_a")?,
-            self.find_node(node_b)
-                .map(|n| n.index)
-                .ok_or("can't find node_b")?,
-            weight,
-        ))
-    }
-
-    fn print_node<Writer: Write>(&self, writer: &mut Writer, node: &Node<W>, changed: Option<&Vec<DVValue<W>>>) -> Result<(), Box<dyn Error>> {
-        let names = self.node_names();
-        writeln!(writer, "<table>\n\t<tr>")?;
-        writeln!(writer, "\t\t<th>{}</th>", node.name)?;
-
-        for sub_node in &self.nodes {
-            writeln!(writer, "\t\t<th>{}</th>", sub_node.name)?;
-        }
-
-        writeln!(writer, "\t</tr>\n\t<tr>\n\t\t<th>{}</th>",node.name)?;
-        // If there's a new dv, run the more complex algorithm
-        if let Some(new_dv) = changed {
-            for (index, new_value) in new_dv.iter().enumerate() {
-                if new_value == node.dv.get(index).unwrap() {
-                    writeln!(writer, "\t\t<td>{}</td>", new_value.write_html_long(&names))?;
-                } else {
-                    writeln!(
-                        writer,
-                        "\t\t<td>{}&#8594;{}</td>",
-                        node.dv.get(index).unwrap().write_html_long(&names),
-                        new_value.write_html_long(&names)
-                    )?;
-                }
-            }
-        } else {
-            for new_value in &node.dv {
-                writeln!(writer, "\t\t<td>{}</td>", new_value.write_html_long(&names))?;
-            }
-        }
-
-        writeln!(writer, "\t</tr>")?;
-
-
+    let command = match positional.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["run", scenario] => Command::Run(scenario.to_string()),
+        ["run"] => return Err("run needs a scenario".into()),
+        ["trace", scenario, from, to] => Command::Trace(scenario.to_string(), from.to_string(), to.to_string()),
+        ["trace", ..] => return Err("trace needs a scenario and the two nodes".into()),
+        ["compare", scenario] => Command::Compare(scenario.to_string()),
+        ["compare"] => return Err("compare needs a scenario".into()),
+        ["list"] => Command::List,
+        ["render"] => Command::Render,
+        ["help"] | [] => Command::Help,
+        other => return Err(format!("unexpected arguments: {}", other.join(" ")).into()),
+    };
+
+    Ok(Args { command, output, prefix, formats, scenarios, chrome, protocol, engine, update_order, order, horizon, algebra, max_metric, hold_down, max_generations, seed, verify, ecmp, tie_break, at })
+}
 
-        for neighbor in &node.neighbors {
-            writeln!(
-                writer,
-                "\t<tr>\n\t\t<th>{}</th>",
-                names.get(&neighbor.index).unwrap()
-            )?;
+/// Lists the scenario files in a directory, sorted by name.
+fn scenario_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
 
-            for v in &neighbor.dv {
-                writeln!(writer, "\t\t<td>{}</td>", v.write_html_long(&names))?;
-            }
+    for entry in fs::read_dir(dir).map_err(|e| format!("can't read {}: {}", dir.display(), e))? {
+        let path = entry?.path();
 
-            writeln!(writer, "\t</tr>")?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") | Some("json") => files.push(path),
+            _ => {}
         }
+    }
 
-        writeln!(writer, "</table>")?;
+    files.sort();
+    Ok(files)
+}
 
-        Ok(())
+/// A scenario argument is either a path to a file or the stem of a file in the scenarios directory.
+fn resolve_scenario(args: &Args, scenario: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let direct = PathBuf::from(scenario);
+    if direct.is_file() {
+        return Ok(direct);
     }
 
-    fn build_world(
-        &self,
-        relations: &HashMap<(usize, usize), W>,
-        main_dvs: &HashMap<usize, Vec<DVValue<W>>>,
-        inbox_dvs: &HashMap<usize, Vec<DVValue<W>>>,
-        updated_nodes: &HashSet<usize>,
-        advance_generation: bool
-    ) -> Self {
-        let mut nodes: Vec<Node<W>> = Vec::new();
-        let mut has_updates: HashSet<usize> = HashSet::new();
-
-        for node_index in updated_nodes {
-            Self::update_has_updates(&mut has_updates, relations, *node_index);
+    for extension in &["toml", "json"] {
+        let candidate = args.scenarios.join(format!("{}.{}", scenario, extension));
+        if candidate.is_file() {
+            return Ok(candidate);
         }
+    }
 
-        for node in &self.nodes {
-            let mut neighbors:Vec<Neighbor<W>> = Vec::new();
-
-            for ((node_a, node_b), new_w) in relations {
-                if *node_a == node.index {
-                    neighbors.push(Neighbor{
-                        index: *node_b,
-                        direct_cost: new_w.to_owned(),
-                        dv: inbox_dvs.get(node_b).unwrap().to_owned()
-                    });
-                }
-            }
+    Err(format!("can't find scenario {}", scenario).into())
+}
 
-            neighbors.sort_by_key(|n| n.index);
+/// Loads a scenario and applies the overrides from the command line.
+fn load(args: &Args, scenario: &str) -> Result<Scenario, Box<dyn Error>> {
+    let mut scenario = Scenario::load(resolve_scenario(args, scenario)?.as_path())?;
+    if let Some(protocol) = args.protocol {
+        scenario.options.protocol = protocol;
+    }
+    if let Some(engine) = args.engine {
+        scenario.options.engine = engine;
+    }
+    if let Some(update_order) = args.update_order {
+        scenario.options.update_order = update_order;
+    }
+    if let Some(order) = &args.order {
+        scenario.options.order = order.clone();
+    }
+    if let Some(horizon) = args.horizon {
+        scenario.options.horizon = horizon;
+    }
+    if let Some(algebra) = args.algebra {
+        scenario.options.algebra = algebra;
+    }
+    if let Some(max_metric) = args.max_metric {
+        scenario.options.max_metric = Some(max_metric);
+    }
+    if let Some(hold_down) = args.hold_down {
+        scenario.options.hold_down = Some(hold_down);
+    }
+    if let Some(max_generations) = args.max_generations {
+        scenario.options.max_generations = max_generations;
+    }
+    if let Some(seed) = args.seed {
+        scenario.options.seed = Some(seed);
+    }
+    if !args.verify {
+        scenario.options.verify = false;
+    }
+    if args.ecmp {
+        scenario.options.ecmp = true;
+    }
+    if let Some(tie_break) = args.tie_break {
+        scenario.options.tie_break = tie_break;
+    }
+    if scenario.uses_seed() && scenario.options.seed.is_none() {
+        scenario.options.seed = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64);
+    }
 
-            nodes.push(Node {
-                name: node.name.to_owned(),
-                dv: main_dvs.get(&node.index).unwrap().to_owned(),
-                index: node.index,
-                has_updates: has_updates.contains(&node.index),
-                neighbors
-            });
-        }
+    Ok(scenario)
+}
 
-        let generation =
-            if advance_generation {
-                self.generation + 1
-            } else {
-                self.generation
-            };
+/// Runs a scenario writing its pages with `prefix`, printing the seed when it matters.
+fn simulate<W: Metric>(args: &Args, scenario: &Scenario, prefix: &str) -> Result<(World<W>, Convergence), Box<dyn Error>> {
+    let mut html_factory = HtmlFiles::new(args.output.to_str().ok_or("output path isn't utf-8")?, prefix)
+        .with_formats(&args.formats)
+        .with_chrome(args.chrome.as_deref());
 
-        World { nodes, generation }
+    let result = scenario.run(&mut html_factory)?;
+    if let (true, Some(seed)) = (scenario.uses_seed(), scenario.options.seed) {
+        println!("{}: seed {}", scenario.name, seed);
     }
 
-    fn copy_relations(&self) -> HashMap<(usize, usize), W> {
-        let mut relations: HashMap<(usize, usize), W> = HashMap::new();
-
-        for node in &self.nodes {
-            for neighbors in &node.neighbors {
-                relations.insert((node.index, neighbors.index), neighbors.direct_cost.to_owned());
-            }
-        }
+    Ok(result)
+}
 
-        relations
+/// Runs a scenario with the overrides from the command line. With `trace` a packet is sent
+/// between the two nodes, and the final route between them is printed.
+fn run(args: &Args, scenario: &str, trace: Option<(&str, &str)>) -> Result<(), Box<dyn Error>> {
+    let mut scenario = load(args, scenario)?;
+    if let Some((from, to)) = trace {
+        scenario.packets.push(PacketSpec { from: from.to_owned(), to: to.to_owned(), at: args.at });
     }
 
-    fn copy_dvs(&self) -> HashMap<usize, Vec<DVValue<W>>> {
-        let mut dvs: HashMap<usize, Vec<DVValue<W>>> = HashMap::new();
+    match scenario.options.algebra {
+        Algebra::ShortestPath => run_with::<u32>(args, &scenario, trace),
+        Algebra::WidestPath => run_with::<Bandwidth>(args, &scenario, trace),
+        Algebra::MostReliable => run_with::<Reliability>(args, &scenario, trace),
+        Algebra::HopBounded => run_with::<HopBounded>(args, &scenario, trace)
+    }
+}
 
-        for node in &self.nodes {
-            let mut dv = Vec::new();
+/// Runs a loaded scenario with the metric of its algebra and prints what happened.
+fn run_with<W: Metric>(args: &Args, scenario: &Scenario, trace: Option<(&str, &str)>) -> Result<(), Box<dyn Error>> {
+    let prefix = args.prefix.as_deref().unwrap_or(scenario.name.as_str());
+    let (world, convergence) = simulate::<W>(args, scenario, prefix)?;
 
-            for v in &node.dv {
-                dv.push(v.clone());
-            }
+    println!("{}: {} at t={}, pages in {}", scenario.name, convergence, world.generation(), args.output.display());
+    for run in world.runs() {
+        println!("{}: {}", scenario.name, run.describe());
+    }
+    for counting in world.counting_report() {
+        println!("{}: {}", scenario.name, counting);
+    }
+    if !world.loop_counts().is_empty() {
+        let counts: Vec<String> = world.loop_counts()
+            .iter()
+            .map(|(generation, count)| format!("t={} ({})", generation, count))
+            .collect();
 
-            dvs.insert(node.index, dv);
+        println!("{}: forwarding loops at {}", scenario.name, counts.join(", "));
+    }
+    for check in world.route_check_report() {
+        println!("{}: {}", scenario.name, check);
+    }
+    for packet in world.packet_report() {
+        println!("{}: {}", scenario.name, packet);
+    }
+    if let Some((from, to)) = trace {
+        for branch in world.trace(from, to)? {
+            println!(
+                "{}: traceroute {} -> {} at t={}: {}",
+                scenario.name,
+                from,
+                to,
+                world.generation(),
+                branch.describe(&world.node_names())
+            );
         }
+    }
 
-        dvs
+    match scenario.unsettled(&world, convergence) {
+        Some(unsettled) => Err(format!("{}: {}", scenario.name, unsettled).into()),
+        None => Ok(())
     }
+}
 
-    fn update_has_updates(has_updates: &mut HashSet<usize>, relations: &HashMap<(usize, usize), W>, node: usize){
-        for ((node_a, node_b), _) in relations {
-            if *node_a == node {
-                has_updates.insert(*node_b);
-            }
-        }
+/// Runs a scenario with every protocol, the pages get the protocol after the prefix.
+/// Prints how many rounds and messages every run took with each of them, and where
+/// they counted to infinity. Protocols the scenario's engine can't run are skipped.
+fn compare(args: &Args, scenario: &str) -> Result<(), Box<dyn Error>> {
+    let scenario = load(args, scenario)?;
+
+    match scenario.options.algebra {
+        Algebra::ShortestPath => compare_with::<u32>(args, &scenario),
+        Algebra::WidestPath => compare_with::<Bandwidth>(args, &scenario),
+        Algebra::MostReliable => compare_with::<Reliability>(args, &scenario),
+        Algebra::HopBounded => compare_with::<HopBounded>(args, &scenario)
     }
+}
 
-    fn apply_operations(&self, html_factory:&mut HtmlFiles, operations: Vec<Operation<W>>) -> Result<Self, Box<dyn Error>> {
-        let mut relations: HashMap<(usize, usize), W> = self.copy_relations();
-        let mut new_dvs: HashMap<usize, Vec<DVValue<W>>> = self.copy_dvs();
-        let mut updated_nodes: HashSet<usize> = HashSet::new();
-
-        for op in operations {
-            match op {
-                Operation::ChangeWeight(node_a, node_b, new_w) => {
-                    relations.insert((node_a, node_b), new_w.to_owned());
-                    relations.insert((node_b, node_a), new_w.to_owned());
-
-                    new_dvs.insert(
-                        node_a,
-                        modify_dv(
-                            new_dvs.get(&node_a).unwrap(),
-                            node_b,
-                            new_w.to_owned(),
-                        ),
-                    );
-
-                    new_dvs.insert(
-                        node_b,
-                        modify_dv(
-                            new_dvs.get(&node_b).unwrap(),
-                            node_a,
-                            new_w.to_owned(),
-                        ),
-                    );
-
-                    updated_nodes.insert(node_a);
-                    updated_nodes.insert(node_b);
-                }
+/// Compares the protocols on a loaded scenario with the metric of its algebra.
+fn compare_with<W: Metric>(args: &Args, scenario: &Scenario) -> Result<(), Box<dyn Error>> {
+    let prefix = args.prefix.as_deref().unwrap_or(scenario.name.as_str());
+    let mut results = Vec::new();
+
+    let protocols = [
+        (Protocol::DistanceVector, "dv"),
+        (Protocol::PathVector, "pv"),
+        (Protocol::Dsdv, "dsdv"),
+        (Protocol::Dual, "dual"),
+        (Protocol::LinkState, "ls")
+    ];
+
+    for (protocol, short) in protocols {
+        let mut with_protocol = scenario.clone();
+        with_protocol.options.protocol = protocol;
+        with_protocol.options.ecmp &= protocol == Protocol::DistanceVector;
+
+        if with_protocol.options.engine == Engine::Events {
+            if let Err(e) = check_events(&with_protocol.options) {
+                println!("{}: {} skipped: {}", scenario.name, protocol, e);
+                continue;
             }
         }
 
-        let print_world = self.build_world(
-            &relations,
-            &new_dvs,
-            &self.copy_dvs(),
-            &updated_nodes,
-            false
+        let (world, convergence) = simulate::<W>(args, &with_protocol, format!("{}_{}", prefix, short).as_str())?;
+        println!(
+            "{}: {} {} at t={}, {} messages",
+            scenario.name,
+            protocol,
+            convergence,
+            world.generation(),
+            world.messages()
         );
-
-        html_factory.create(|writer|{
-            print_world.print_state(writer)
-        })?;
-
-
-        Ok(self.build_world(
-            &relations,
-            &new_dvs,
-            &new_dvs,
-            &updated_nodes,
-            false
-        ))
-    }
-
-    fn print_state<Writer: Write>(&self, writer: &mut Writer) -> Result<(), Box<dyn Error>> {
-        writeln!(writer, "<h2>t={}</h2>", self.generation)?;
-
-        for node in &self.nodes {
-            self.print_node(writer, node, None)?;
+        for line in world.counting_report().into_iter().chain(world.route_check_report()) {
+            println!("{}: {}: {}", scenario.name, protocol, line);
         }
-
-        Ok(())
-    }
-
-    fn run_simulation(&self, html_factory: &mut HtmlFiles) -> Result<NewState<W>, Box<dyn Error>> {
-        let mut writer:Vec<u8> = Vec::new();
-
-        writeln!(writer, "<h2>t={}</h2>", self.generation + 1)?;
-
-        let mut updated_nodes: HashSet<usize> = HashSet::new();
-        let mut new_dvs: HashMap<usize, Vec<DVValue<W>>> = self.copy_dvs();
-        let names = self.node_names();
-
-        for node in &self.nodes {
-            if node.has_updates {
-                let mut new_dv = Vec::new();
-                let mut index: usize = 0;
-
-                let mut lines: Vec<String> = Vec::new();
-
-                for v_old in &node.dv {
-                    if index == node.index {
-                        new_dv.push(DVValue::SameNode);
-                    } else {
-                        // For debug printing
-                        let mut formula =
-                            HtmlFormula::new(index, node.index);
-
-                        for neighbour in &node.neighbors {
-                            if neighbour.index == index {
-                                formula.add_direct(
-                                    neighbour.index,
-                                    node.index,
-                                    neighbour
-                                        .direct_cost
-                                        .to_owned()
-                                );
-                            } else {
-                                formula.add_indirect(
-                                    neighbour.index,
-                                    node.index,
-                                    neighbour
-                                        .direct_cost
-                                        .to_owned(),
-                                    index,
-                                    neighbour.index,
-                                    neighbour.dv
-                                        .get(index)
-                                        .unwrap()
-                                        .into()
-                                );
-                            }
-                        }
-
-                        let v = formula.min_vector();
-
-                        if v != v_old.to_owned() {
-                            updated_nodes.insert(node.index);
-                        }
-
-                        new_dv.push(v);
-                        lines.push(formula.render(&names));
-                    }
-
-                    index += 1;
-                }
-
-                self.print_node(&mut writer, node, Some(&new_dv))?;
-                writeln!(writer, "<div class=\"details\">")?;
-                for line in lines {
-                    writeln!(writer, "\t<div>{}</div>", line)?;
-                }
-                writeln!(writer, "</div>")?;
-
-                new_dvs.insert(node.index, new_dv);
-            } else {
-                self.print_node(&mut writer, node, None)?;
-                new_dvs.insert(node.index, node.dv.clone());
-            }
+        if let Some(unsettled) = with_protocol.unsettled(&world, convergence) {
+            println!("{}: {}: {}", scenario.name, protocol, unsettled);
         }
 
-        html_factory.create(|w| {
-            w.write_all(writer.as_slice())?;
-            Ok(())
-        })?;
-
-        if updated_nodes.is_empty() {
-            Ok(NewState::NotChanged)
-        } else {
-            Ok(NewState::Changed(self.build_world(
-                &self.copy_relations(),
-                &new_dvs,
-                &new_dvs,
-                &updated_nodes,
-                true
-            )))
-        }
+        results.push((protocol, world));
     }
-}
-
 
+    let runs = results.iter().map(|(_, world)| world.runs().len()).max().unwrap_or(0);
+    for run in 0..runs {
+        let parts: Vec<String> = results
+            .iter()
+            .map(|(protocol, world)| match world.runs().get(run) {
+                Some(stats) => format!("{} {} rounds, {} messages", protocol, stats.rounds(), stats.messages()),
+                None => format!("{} didn't get there", protocol)
+            })
+            .collect();
 
-fn run_until_stable(html_factory: &mut HtmlFiles, world: World<u32>) -> Result<World<u32>, Box<dyn Error>> {
-    match world.run_simulation(html_factory)? {
-        NewState::Changed(w2) => run_until_stable(html_factory, w2),
-        // When no-change advance the generation on by 1
-        NewState::NotChanged => Ok(World{
-            nodes: world.nodes,
-            generation: world.generation+1
-        })
+        println!("{}: run {}: {}", scenario.name, run + 1, parts.join("; "));
     }
-}
-
-fn exc2(p:&Path) -> Result<(), Box<dyn Error>> {
-    let mut html_factory = HtmlFiles::new(p.to_str().unwrap(), "exc2");
-
-    let world: World<u32> = World::new(vec!("A", "B", "C", "D", "E", "F", "G", "H"));
-    //println!("op:{:#?}", world.add_interface("A","B",12)?);
-
-    let init = world.apply_operations(&mut html_factory, vec!(
-        world.add_interface("A", "D", 3)?,
-        world.add_interface("A", "G", 1)?,
-        world.add_interface("B", "E", 2)?,
-        world.add_interface("B", "H", 1)?,
-        world.add_interface("C", "D", 1)?,
-        world.add_interface("C", "F", 2)?,
-        world.add_interface("D", "G", 6)?,
-        world.add_interface("D", "F", 5)?,
-        world.add_interface("E", "F", 1)?,
-        world.add_interface("E", "H", 3)?,
-        world.add_interface("F", "H", 8)?,
-    ))?;
-
-    let stable = run_until_stable(&mut html_factory, init)?;
-
-    let new_init = stable.apply_operations(&mut html_factory, vec!(
-        world.add_interface("C", "F", 30)?
-    ))?;
-
-    run_until_stable(&mut html_factory, new_init)?;
+    println!("{}: pages in {}", scenario.name, args.output.display());
 
     Ok(())
 }
 
-fn exc3(p:&Path) -> Result<(), Box<dyn Error>> {
-    let mut html_factory = HtmlFiles::new(p.to_str().unwrap(), "exc3");
-
-    let world: World<u32> = World::new(vec!("A", "B", "C", "D"));
-    //println!("op:{:#?}", world.add_interface("A","B",12)?);
-
-    let init = world.apply_operations(&mut html_factory, vec!(
-        world.add_interface("A", "B", 2)?,
-        world.add_interface("B", "C", 7)?,
-        world.add_interface("C", "D", 4)?,
-        world.add_interface("A", "D", 8)?,
-        world.add_interface("B", "D", 9)?
-    ))?;
-
-    let world1_done = run_until_stable(&mut html_factory, init)?;
-
-    let world2 = world1_done.apply_operations(&mut html_factory, vec!(
-        world1_done.add_interface("B", "D", 80)?
-    ))?;
+fn list(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+
+    for path in scenario_files(args.scenarios.as_path())? {
+        match Scenario::load(path.as_path()) {
+            Ok(scenario) => println!(
+                "{}\t{} nodes, {} links, {} steps",
+                scenario.name,
+                scenario.nodes.len(),
+                scenario.links.len(),
+                scenario.steps.len()
+            ),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}", e);
+            }
+        }
+    }
 
-    run_until_stable(&mut html_factory, world2)?;
+    if failed > 0 {
+        return Err(format!("{} scenarios failed to load", failed).into());
+    }
 
     Ok(())
 }
 
-fn tir1(p:&Path) -> Result<(), Box<dyn Error>> {
-    let mut html_factory = HtmlFiles::new(p.to_str().unwrap(), "tir2");
-
-    let world: World<u32> = World::new(vec!("A", "B", "C", "D"));
-    //println!("op:{:#?}", world.add_interface("A","B",12)?);
+fn render(args: &Args) -> Result<(), Box<dyn Error>> {
+    let chrome = find_chrome(args.chrome.as_deref())?;
+    let mut rendered = 0;
 
-    let init = world.apply_operations(&mut html_factory, vec!(
-        world.add_interface("A", "B", 2)?,
-        world.add_interface("A", "D", 10)?,
-        world.add_interface("B", "C", 1)?,
-        world.add_interface("B", "D", 20)?,
-        world.add_interface("C", "D", 2)?
-    ))?;
+    for entry in fs::read_dir(&args.output).map_err(|e| format!("can't read {}: {}", args.output.display(), e))? {
+        let path = entry?.path();
+        let file_name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.ends_with(".html") => name.to_owned(),
+            _ => continue,
+        };
 
-    let world1_done = run_until_stable(&mut html_factory, init)?;
+        if let Some(prefix) = &args.prefix {
+            if !file_name.starts_with(format!("{}_", prefix).as_str()) {
+                continue;
+            }
+        }
 
-    let world2 = world1_done.apply_operations(&mut html_factory, vec!(
-        world1_done.add_interface("C", "D", 80)?
-    ))?;
+        render_pdf(chrome.as_str(), args.output.as_path(), file_name.as_str())?;
+        rendered += 1;
+    }
 
-    run_until_stable(&mut html_factory, world2)?;
+    println!("rendered {} pages in {}", rendered, args.output.display());
 
     Ok(())
 }
 
-fn tir2(p:&Path) -> Result<(), Box<dyn Error>> {
-    let mut html_factory = HtmlFiles::new(p.to_str().unwrap(), "tir2");
-
-    let world: World<u32> = World::new(vec!("A", "B", "C", "D"));
-    //println!("op:{:#?}", world.add_interface("A","B",12)?);
-
-    let init = world.apply_operations(&mut html_factory, vec!(
-        world.add_interface("A", "B", 1)?,
-        world.add_interface("A", "D", 4)?,
-        world.add_interface("B", "C", 7)?,
-        world.add_interface("B", "D", 2)?,
-        world.add_interface("C", "D", 3)?
-    ))?;
-
-    let world1_done = run_until_stable(&mut html_factory, init)?;
-
-    let world2 = world1_done.apply_operations(&mut html_factory, vec!(
-        world1_done.add_interface("B", "D", 60)?
-    ))?;
-
-    run_until_stable(&mut html_factory, world2)?;
-
-    Ok(())
+fn execute(args: &Args) -> Result<(), Box<dyn Error>> {
+    match &args.command {
+        Command::Run(scenario) => run(args, scenario, None),
+        Command::Trace(scenario, from, to) => run(args, scenario, Some((from, to))),
+        Command::Compare(scenario) => compare(args, scenario),
+        Command::List => list(args),
+        Command::Render => render(args),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let output_path = Path::new("/Users/ilya/Desktop/dvr4");
-    //exc2(output_path)?;
-    //exc3(output_path)?;
-    tir2(output_path);
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
 
-    Ok(())
+    if let Err(e) = execute(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
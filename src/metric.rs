@@ -0,0 +1,296 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+
+/// A routing algebra: how the weights of links combine along a path, and which of two
+/// paths a node prefers. Nodes pick the preferred of their neighbors' paths, so plain
+/// weights add up and the smallest sum wins.
+pub trait Metric: Clone + Eq + Hash + Debug + Display {
+    /// The weight of going through `self` and then `other`
+    fn combine(&self, other: &Self) -> Self;
+
+    /// `Less` when `self` is the better path
+    fn prefer(&self, other: &Self) -> Ordering;
+
+    /// The metric for a link weight from a scenario file
+    fn from_weight(weight: u32) -> Result<Self, String>;
+
+    /// The metric for the scenario's maximum metric, paths that aren't better are unreachable
+    fn from_max_metric(max_metric: u32) -> Result<Self, String> {
+        Self::from_weight(max_metric)
+    }
+
+    /// Writes `terms` combined along a path, like `a+b`
+    fn combine_html(terms: &[String]) -> String;
+
+    /// The function the formulas pick the best path with, like `min`
+    fn prefer_html() -> &'static str;
+
+    /// How a node's distance to itself is written
+    fn identity_html() -> &'static str;
+
+    /// How a distance without any path is written
+    fn unreachable_html() -> &'static str;
+
+    /// The note on a path that is no better than `self` as the maximum metric
+    fn limit_html(&self) -> String {
+        format!("&#8805;{}", self)
+    }
+}
+
+/// Shortest paths, link weights add up and the smallest sum wins.
+impl Metric for u32 {
+    // Counting to infinity without a maximum metric would overflow eventually
+    fn combine(&self, other: &Self) -> Self {
+        self.saturating_add(*other)
+    }
+
+    fn prefer(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    fn from_weight(weight: u32) -> Result<Self, String> {
+        Ok(weight)
+    }
+
+    fn combine_html(terms: &[String]) -> String {
+        terms.join("+")
+    }
+
+    fn prefer_html() -> &'static str {
+        "min"
+    }
+
+    fn identity_html() -> &'static str {
+        "0"
+    }
+
+    fn unreachable_html() -> &'static str {
+        "&infin;"
+    }
+}
+
+/// Widest paths, a path is as wide as its narrowest link and the widest one wins.
+/// A node reaches itself with unlimited bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bandwidth(u32);
+
+impl Display for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Metric for Bandwidth {
+    fn combine(&self, other: &Self) -> Self {
+        Bandwidth(self.0.min(other.0))
+    }
+
+    fn prefer(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+
+    fn from_weight(weight: u32) -> Result<Self, String> {
+        Ok(Bandwidth(weight))
+    }
+
+    fn combine_html(terms: &[String]) -> String {
+        format!("min({})", terms.join(", "))
+    }
+
+    fn prefer_html() -> &'static str {
+        "max"
+    }
+
+    fn identity_html() -> &'static str {
+        "&infin;"
+    }
+
+    fn unreachable_html() -> &'static str {
+        "0"
+    }
+
+    fn limit_html(&self) -> String {
+        format!("&#8804;{}", self)
+    }
+}
+
+/// Billionths, enough to keep the product of four percentages exact.
+const RELIABILITY_SCALE: u64 = 1_000_000_000;
+
+/// Most reliable paths, the probabilities of the links multiply and the most likely path
+/// wins. Link weights are percentages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reliability(u64);
+
+impl Display for Reliability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Four decimals at most, the rest only matters for comparisons
+        let rounded = (self.0 + RELIABILITY_SCALE / 20_000) / (RELIABILITY_SCALE / 10_000);
+        let decimals = format!("{:04}", rounded % 10_000);
+        let decimals = decimals.trim_end_matches('0');
+
+        if decimals.is_empty() {
+            write!(f, "{}", rounded / 10_000)
+        } else {
+            write!(f, "{}.{}", rounded / 10_000, decimals)
+        }
+    }
+}
+
+impl Metric for Reliability {
+    // Both are at most `RELIABILITY_SCALE`, so the product stays below 10^18 and fits
+    fn combine(&self, other: &Self) -> Self {
+        Reliability((self.0 * other.0 + RELIABILITY_SCALE / 2) / RELIABILITY_SCALE)
+    }
+
+    fn prefer(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+
+    fn from_weight(weight: u32) -> Result<Self, String> {
+        if weight > 100 {
+            return Err(format!("a reliability of {}% is more than 100%", weight));
+        }
+
+        Ok(Reliability(weight as u64 * RELIABILITY_SCALE / 100))
+    }
+
+    fn combine_html(terms: &[String]) -> String {
+        terms.join("&times;")
+    }
+
+    fn prefer_html() -> &'static str {
+        "max"
+    }
+
+    fn identity_html() -> &'static str {
+        "1"
+    }
+
+    fn unreachable_html() -> &'static str {
+        "0"
+    }
+
+    fn limit_html(&self) -> String {
+        format!("&#8804;{}", self)
+    }
+}
+
+/// Paths with the fewest hops, the link weights add up and break ties between paths
+/// with as many hops. The maximum metric bounds the hops, like RIP's 16. Written as
+/// hops and cost, `2h/5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HopBounded {
+    hops: u32,
+    cost: u32
+}
+
+impl Display for HopBounded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}h/{}", self.hops, self.cost)
+    }
+}
+
+impl Metric for HopBounded {
+    fn combine(&self, other: &Self) -> Self {
+        HopBounded { hops: self.hops.saturating_add(other.hops), cost: self.cost.saturating_add(other.cost) }
+    }
+
+    fn prefer(&self, other: &Self) -> Ordering {
+        self.hops.cmp(&other.hops).then(self.cost.cmp(&other.cost))
+    }
+
+    fn from_weight(weight: u32) -> Result<Self, String> {
+        Ok(HopBounded { hops: 1, cost: weight })
+    }
+
+    fn from_max_metric(max_metric: u32) -> Result<Self, String> {
+        Ok(HopBounded { hops: max_metric, cost: 0 })
+    }
+
+    fn combine_html(terms: &[String]) -> String {
+        terms.join("+")
+    }
+
+    fn prefer_html() -> &'static str {
+        "min"
+    }
+
+    fn identity_html() -> &'static str {
+        "0"
+    }
+
+    fn unreachable_html() -> &'static str {
+        "&infin;"
+    }
+
+    fn limit_html(&self) -> String {
+        format!("&#8805;{}h", self.hops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weight<W: Metric>(weight: u32) -> W {
+        W::from_weight(weight).unwrap()
+    }
+
+    #[test]
+    fn shortest_paths_add_up() {
+        assert_eq!(3u32.combine(&4), 7);
+        assert_eq!(3u32.prefer(&4), Ordering::Less);
+        assert_eq!(u32::MAX.combine(&1), u32::MAX);
+    }
+
+    #[test]
+    fn widest_paths_keep_the_narrowest_link() {
+        let (narrow, wide) = (weight::<Bandwidth>(10), weight::<Bandwidth>(100));
+
+        assert_eq!(narrow.combine(&wide), narrow);
+        assert_eq!(wide.prefer(&narrow), Ordering::Less);
+    }
+
+    #[test]
+    fn reliabilities_multiply() {
+        let (half, most) = (weight::<Reliability>(50), weight::<Reliability>(90));
+
+        assert_eq!(half.combine(&most).to_string(), "0.45");
+        assert_eq!(most.prefer(&half), Ordering::Less);
+        assert_eq!(weight::<Reliability>(100).combine(&half), half);
+        assert!(Reliability::from_weight(101).is_err());
+    }
+
+    #[test]
+    fn reliabilities_round_to_billionths() {
+        let third = Reliability(RELIABILITY_SCALE / 3);
+
+        // 333333333 squared is 111111110.88... billionths, rounded up
+        assert_eq!(third.combine(&third), Reliability(111_111_111));
+        assert_eq!(third.to_string(), "0.3333");
+        assert_eq!(weight::<Reliability>(99).combine(&weight(99)).combine(&weight(99)).to_string(), "0.9703");
+    }
+
+    #[test]
+    fn fewer_hops_win_before_the_cost() {
+        let two_hops = weight::<HopBounded>(1).combine(&weight(1));
+        let one_hop = weight::<HopBounded>(5);
+
+        assert_eq!(two_hops.to_string(), "2h/2");
+        assert_eq!(one_hop.prefer(&two_hops), Ordering::Less);
+        assert_eq!(two_hops.prefer(&weight::<HopBounded>(1).combine(&weight(3))), Ordering::Less);
+    }
+
+    #[test]
+    fn the_hop_bound_caps_the_hops_only() {
+        let cap = HopBounded::from_max_metric(16).unwrap();
+        let fifteen = (1..15).fold(weight::<HopBounded>(100), |path, _| path.combine(&weight(100)));
+
+        assert_eq!(fifteen.to_string(), "15h/1500");
+        assert_eq!(fifteen.prefer(&cap), Ordering::Less);
+        assert_eq!(fifteen.combine(&weight(1)).prefer(&cap), Ordering::Greater);
+        assert_eq!(cap.limit_html(), "&#8805;16h");
+    }
+}
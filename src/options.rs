@@ -0,0 +1,302 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// How a node treats routes it learned through the neighbor it is advertising to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HorizonMode {
+    /// Advertise the whole table to everyone
+    #[default]
+    Plain,
+    /// Leave out routes whose next hop is the receiving neighbor
+    SplitHorizon,
+    /// Advertise infinity for routes whose next hop is the receiving neighbor
+    PoisonReverse,
+}
+
+impl FromStr for HorizonMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(HorizonMode::Plain),
+            "split_horizon" => Ok(HorizonMode::SplitHorizon),
+            "poison_reverse" => Ok(HorizonMode::PoisonReverse),
+            _ => Err(format!("unknown horizon {}, expected plain, split_horizon or poison_reverse", s))
+        }
+    }
+}
+
+/// The routing protocol the nodes run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// Nodes send their tables to their neighbors (RIP-like)
+    #[default]
+    DistanceVector,
+    /// Distance vector where every route carries its whole path, and nodes reject
+    /// paths they're already on (BGP-like)
+    PathVector,
+    /// Distance vector where routes carry the destination's sequence number, and newer
+    /// numbers win over lower costs
+    Dsdv,
+    /// EIGRP's diffusing update algorithm: routes only switch to feasible successors, and
+    /// the neighbors are queried when there's none
+    Dual,
+    /// Nodes flood their links to everyone and compute shortest paths themselves (OSPF-like).
+    /// Runs on the lockstep engine, the horizon, timers, hold-down and update order don't apply
+    LinkState,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "distance_vector" => Ok(Protocol::DistanceVector),
+            "path_vector" => Ok(Protocol::PathVector),
+            "dsdv" => Ok(Protocol::Dsdv),
+            "dual" => Ok(Protocol::Dual),
+            "link_state" => Ok(Protocol::LinkState),
+            _ => Err(format!("unknown protocol {}, expected distance_vector, path_vector, dsdv, dual or link_state", s))
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::DistanceVector => write!(f, "distance vector"),
+            Protocol::PathVector => write!(f, "path vector"),
+            Protocol::Dsdv => write!(f, "DSDV"),
+            Protocol::Dual => write!(f, "DUAL"),
+            Protocol::LinkState => write!(f, "link state")
+        }
+    }
+}
+
+/// What drives the simulation forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    /// Every node with updates recomputes at once, one generation at a time
+    #[default]
+    Lockstep,
+    /// Tables travel over the links with their delays and nodes recompute when one arrives
+    Events,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lockstep" => Ok(Engine::Lockstep),
+            "events" => Ok(Engine::Events),
+            _ => Err(format!("unknown engine {}, expected lockstep or events", s))
+        }
+    }
+}
+
+/// The order nodes update in within a generation of the lockstep engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOrder {
+    /// Every node computes from the same snapshot (Jacobi)
+    #[default]
+    Simultaneous,
+    /// One node at a time in the configured order, later nodes see the tables
+    /// of earlier ones right away (Gauss-Seidel)
+    Sequential,
+    /// Like sequential, in a new random order every generation
+    Random,
+}
+
+impl FromStr for UpdateOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "simultaneous" => Ok(UpdateOrder::Simultaneous),
+            "sequential" => Ok(UpdateOrder::Sequential),
+            "random" => Ok(UpdateOrder::Random),
+            _ => Err(format!("unknown update order {}, expected simultaneous, sequential or random", s))
+        }
+    }
+}
+
+/// Which neighbor a node picks when several give the same shortest distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// The neighbor with the lowest index, the first one in the scenario's node list
+    #[default]
+    LowestIndex,
+    /// The neighbor whose name sorts first
+    LowestName,
+    /// The current next hop when it's one of the tied neighbors, the lowest index otherwise
+    KeepCurrent,
+    /// A seeded random pick, the same one whenever the same neighbors tie for a destination
+    Random,
+}
+
+impl FromStr for TieBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lowest_index" => Ok(TieBreak::LowestIndex),
+            "lowest_name" => Ok(TieBreak::LowestName),
+            "keep_current" => Ok(TieBreak::KeepCurrent),
+            "random" => Ok(TieBreak::Random),
+            _ => Err(format!("unknown tie-breaking {}, expected lowest_index, lowest_name, keep_current or random", s))
+        }
+    }
+}
+
+impl Display for TieBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TieBreak::LowestIndex => write!(f, "lowest index"),
+            TieBreak::LowestName => write!(f, "lowest name"),
+            TieBreak::KeepCurrent => write!(f, "keep current"),
+            TieBreak::Random => write!(f, "random")
+        }
+    }
+}
+
+/// How link weights combine along a path and which path is the best, see `Metric`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algebra {
+    /// Weights add up and the smallest sum wins
+    #[default]
+    ShortestPath,
+    /// Weights are bandwidths, a path is as wide as its narrowest link and the widest wins
+    WidestPath,
+    /// Weights are percentages, they multiply and the most likely path wins
+    MostReliable,
+    /// The fewest hops win, the weights add up and break ties. The maximum metric bounds the hops
+    HopBounded,
+}
+
+impl FromStr for Algebra {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shortest_path" => Ok(Algebra::ShortestPath),
+            "widest_path" => Ok(Algebra::WidestPath),
+            "most_reliable" => Ok(Algebra::MostReliable),
+            "hop_bounded" => Ok(Algebra::HopBounded),
+            _ => Err(format!("unknown algebra {}, expected shortest_path, widest_path, most_reliable or hop_bounded", s))
+        }
+    }
+}
+
+impl Display for Algebra {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algebra::ShortestPath => write!(f, "shortest path"),
+            Algebra::WidestPath => write!(f, "widest path"),
+            Algebra::MostReliable => write!(f, "most reliable"),
+            Algebra::HopBounded => write!(f, "hop-bounded")
+        }
+    }
+}
+
+/// RIP-like route timers, counted in rounds. With RIP's 30 second updates the
+/// defaults match its 180 second timeout and 120 second garbage collection.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timers {
+    /// Rounds without a refresh from the next hop before a route becomes unreachable
+    pub timeout: u32,
+    /// Rounds an unreachable route is kept before it is removed
+    pub garbage_collection: u32,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Timers {
+            timeout: 6,
+            garbage_collection: 4
+        }
+    }
+}
+
+/// Protocol settings for a single simulation, read from the `[options]` table of a scenario.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options<W> {
+    pub protocol: Protocol,
+    pub engine: Engine,
+    pub update_order: UpdateOrder,
+    /// Nodes that update first in sequential order, the rest follow in their usual order
+    pub order: Vec<String>,
+    pub horizon: HorizonMode,
+    pub algebra: Algebra,
+    /// Distances at or above this are unreachable, like RIP's 16. Paths that aren't better
+    /// with the other algebras
+    pub max_metric: Option<W>,
+    pub timers: Option<Timers>,
+    /// Rounds a node ignores other neighbors after a route got worse
+    pub hold_down: Option<u32>,
+    /// The simulation gives up after this many generations of a run, or this much time on the
+    /// event engine
+    pub max_generations: u32,
+    /// Seeds the random loss and duplication of tables on unreliable links, the random update
+    /// order and random tie-breaking
+    pub seed: Option<u64>,
+    /// Compare the tables of every converged run with the shortest paths
+    pub verify: bool,
+    /// Keep every next hop that ties for the shortest distance, distance vector only
+    pub ecmp: bool,
+    /// How a node picks between neighbors that tie for the shortest distance
+    pub tie_break: TieBreak,
+}
+
+impl<W> Default for Options<W> {
+    fn default() -> Self {
+        Options {
+            protocol: Protocol::default(),
+            engine: Engine::default(),
+            update_order: UpdateOrder::default(),
+            order: Vec::new(),
+            horizon: HorizonMode::default(),
+            algebra: Algebra::default(),
+            max_metric: None,
+            timers: None,
+            hold_down: None,
+            max_generations: 1000,
+            seed: None,
+            verify: true,
+            ecmp: false,
+            tie_break: TieBreak::default()
+        }
+    }
+}
+
+impl<W> Options<W> {
+    /// The same options with the maximum metric converted by `convert`.
+    pub fn map_max_metric<V, E>(self, convert: impl FnOnce(W) -> Result<V, E>) -> Result<Options<V>, E> {
+        Ok(Options {
+            protocol: self.protocol,
+            engine: self.engine,
+            update_order: self.update_order,
+            order: self.order,
+            horizon: self.horizon,
+            algebra: self.algebra,
+            max_metric: self.max_metric.map(convert).transpose()?,
+            timers: self.timers,
+            hold_down: self.hold_down,
+            max_generations: self.max_generations,
+            seed: self.seed,
+            verify: self.verify,
+            ecmp: self.ecmp,
+            tie_break: self.tie_break
+        })
+    }
+}
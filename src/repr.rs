@@ -1,9 +1,8 @@
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
-use std::fmt::Display;
-use std::ops::Add;
 
 use crate::cost::{DVValue, Cost};
+use crate::metric::Metric;
 use std::slice::Iter;
 use std::io::Write;
 use std::fs::File;
@@ -11,28 +10,51 @@ use std::path::Path;
 use std::error::Error;
 use std::process::Command;
 use std::fs;
+use std::env;
+use std::str::FromStr;
 
-impl<W: Ord + Clone + Add<Output=W> + Display> DVValue<W> {
+impl<W: Metric> DVValue<W> {
     pub fn write_html_long(&self, names: &BTreeMap<usize, String>) -> String {
         match self {
-            DVValue::Infinity => String::from("&infin;"),
+            DVValue::Infinity => String::from(W::unreachable_html()),
             DVValue::Distance(v, id) => format!(
                 "{}({})",
                 v,
                 names.get(id).unwrap()
             ),
             DVValue::DirectDistance(v) => format!("{}", v),
-            DVValue::SameNode => String::from("0")
+            DVValue::SameNode => String::from(W::identity_html()),
+            DVValue::Path(v, path) => format!(
+                "{}({})",
+                v,
+                path.iter().map(|n| names.get(n).unwrap().as_str()).collect::<Vec<_>>().join(" ")
+            ),
+            DVValue::Dsdv(Cost::Value(v), via, sequence) => format!(
+                "{}({}){}",
+                v,
+                names.get(via).unwrap(),
+                sequence_html(*sequence)
+            ),
+            DVValue::Dsdv(cost, _, sequence) => format!("{}{}", cost.write_html(), sequence_html(*sequence)),
+            DVValue::Multipath(v, hops) => format!(
+                "{}({})",
+                v,
+                hops.iter().map(|n| names.get(n).unwrap().as_str()).collect::<Vec<_>>().join(",")
+            )
         }
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> Cost<W> {
+fn sequence_html(sequence: u32) -> String {
+    format!("<span class=\"sequence\" title=\"destination sequence number\">#{}</span>", sequence)
+}
+
+impl<W: Metric> Cost<W> {
     pub fn write_html(&self) -> String {
         match self {
-            Cost::Infinity => String::from("&infin;"),
+            Cost::Infinity => String::from(W::unreachable_html()),
             Cost::Value(v) => format!("{}", v),
-            Cost::Zero => String::from("0")
+            Cost::Zero => String::from(W::identity_html())
         }
     }
 }
@@ -55,44 +77,106 @@ impl DistanceCalculationRepr for DistanceCalculationElement {
             DistanceCalculationElement::DirectDistance(target, source) =>
                 format!(
                     "C({},{})",
-                    names.get(&source).unwrap(),
-                    names.get(&target).unwrap()
+                    names.get(source).unwrap(),
+                    names.get(target).unwrap()
                 ),
             DistanceCalculationElement::DistanceVector(target, source) =>
                 format!(
                     "d<sub>{}</sub>({})",
-                    names.get(&source).unwrap(),
-                    names.get(&target).unwrap()
+                    names.get(source).unwrap(),
+                    names.get(target).unwrap()
                 )
         }
     }
 }
 
+/// Why the advertised part of a term isn't the neighbor's real distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermNote {
+    SplitHorizon,
+    PoisonReverse,
+    TimedOut,
+    HoldDown,
+    PathLoop,
+    OlderSequence,
+    NotFeasible,
+}
+
+impl TermNote {
+    fn abbreviation(&self) -> &'static str {
+        match self {
+            TermNote::SplitHorizon => "SH",
+            TermNote::PoisonReverse => "PR",
+            TermNote::TimedOut => "TO",
+            TermNote::HoldDown => "HD",
+            TermNote::PathLoop => "PL",
+            TermNote::OlderSequence => "SN",
+            TermNote::NotFeasible => "NF",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            TermNote::SplitHorizon => "suppressed by split horizon",
+            TermNote::PoisonReverse => "poisoned reverse route",
+            TermNote::TimedOut => "neighbor timed out",
+            TermNote::HoldDown => "ignored during hold-down",
+            TermNote::PathLoop => "rejected, the path already goes through this node",
+            TermNote::OlderSequence => "ignored, a newer sequence number is known",
+            TermNote::NotFeasible => "not a feasible successor, the reported distance isn't below the feasible distance",
+        }
+    }
+
+    /// Whether the whole term is left out of the minimum, rather than just its advertised part
+    fn ignores_term(&self) -> bool {
+        matches!(self, TermNote::HoldDown | TermNote::PathLoop | TermNote::OlderSequence | TermNote::NotFeasible)
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct DistanceCalculationTuple<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> {
+pub struct DistanceCalculationTuple<W: Metric, R: DistanceCalculationRepr> {
     description: Vec<R>,
     result: Vec<Cost<W>>,
     through: usize,
-    direct: bool
+    direct: bool,
+    note: Option<TermNote>,
+    max_metric: Option<W>,
+    // The destination sequence number the term comes with, for DSDV
+    sequence: Option<u32>
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> DistanceCalculationTuple<W, R> {
+impl<W: Metric, R: DistanceCalculationRepr> DistanceCalculationTuple<W, R> {
     pub fn sum(&self) -> Cost<W> {
         let mut sum:Cost<W> = Cost::Zero;
 
         for item in &self.result {
-            sum = sum + item.to_owned();
+            sum = sum.add_capped(item.to_owned(), self.max_metric.as_ref());
         }
 
         sum
     }
 
+    /// Whether the maximum metric turned a finite sum into infinity
+    fn is_capped(&self) -> bool {
+        let mut sum:Cost<W> = Cost::Zero;
+
+        for item in &self.result {
+            sum = sum + item.to_owned();
+        }
+
+        sum != Cost::Infinity && self.sum() == Cost::Infinity
+    }
+
     pub fn sum_dv(&self) -> DVValue<W> {
         self.sum().to_dv_value(self.through, self.direct)
     }
+
+    pub fn is_ignored(&self) -> bool {
+        self.note.is_some_and(|note| note.ignores_term())
+    }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> PartialEq
+impl<W: Metric, R: DistanceCalculationRepr> PartialEq
     for DistanceCalculationTuple<W, R>{
 
     fn eq(&self, other: &Self) -> bool {
@@ -100,19 +184,19 @@ impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> Parti
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> Eq
+impl<W: Metric, R: DistanceCalculationRepr> Eq
     for DistanceCalculationTuple<W, R>{
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> PartialOrd
+impl<W: Metric, R: DistanceCalculationRepr> PartialOrd
     for DistanceCalculationTuple<W, R>{
 
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.sum().partial_cmp(&other.sum())
+        Some(self.cmp(other))
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> Ord
+impl<W: Metric, R: DistanceCalculationRepr> Ord
     for DistanceCalculationTuple<W, R> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.sum().cmp(&other.sum())
@@ -120,21 +204,23 @@ impl<W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> Ord
 }
 
 pub trait DistanceCalculationLine
-    <W: Ord + Clone + Add<Output=W> + Display, R: DistanceCalculationRepr> {
+    <W: Metric, R: DistanceCalculationRepr> {
     fn draw_direct(target: usize, src: usize) -> R;
     fn draw_distance(target: usize, src: usize) -> R;
 
     fn get_target(&self) -> usize;
     fn get_source(&self) -> usize;
 
-    fn get_members(&self) -> Iter<DistanceCalculationTuple<W, R>>;
+    fn get_members(&self) -> Iter<'_, DistanceCalculationTuple<W, R>>;
 
     fn render(&self, names: &BTreeMap<usize, String>) -> String {
         let mut result =
             Self::draw_distance(self.get_target(), self.get_source())
                 .to_string(names);
 
-        result += "=min(";
+        result += "=";
+        result += W::prefer_html();
+        result += "(";
 
         let mut first = true;
         for desc in self.get_members() {
@@ -144,19 +230,13 @@ pub trait DistanceCalculationLine
                 result += ", ";
             }
 
-            let mut first2 = true;
-            for item in &desc.description {
-                if first2 {
-                    first2 = false;
-                } else {
-                    result += "+";
-                }
-
-                result += item.to_string(names).as_str();
-            }
+            let items: Vec<String> = desc.description.iter().map(|item| item.to_string(names)).collect();
+            result += W::combine_html(&items).as_str();
         }
 
-        result += ")=min(";
+        result += ")=";
+        result += W::prefer_html();
+        result += "(";
         first = true;
         for desc in self.get_members() {
             if first {
@@ -165,15 +245,42 @@ pub trait DistanceCalculationLine
                 result += ", ";
             }
 
-            let mut first2 = true;
-            for item in &desc.result {
-                if first2 {
-                    first2 = false;
-                } else {
-                    result += "+";
+            let mut items = Vec::new();
+            for (index, item) in desc.result.iter().enumerate() {
+                // The note is about the last item, the advertised distance or a lone direct cost
+                match &desc.note {
+                    Some(note) if !note.ignores_term() && index + 1 == desc.result.len() => {
+                        items.push(format!(
+                            "<span class=\"note\" title=\"{}\">{}<sup>{}</sup></span>",
+                            note.title(),
+                            item.write_html(),
+                            note.abbreviation()
+                        ));
+                    }
+                    _ => items.push(item.write_html())
                 }
+            }
+            let mut term = W::combine_html(&items);
 
-                result += item.write_html().as_str();
+            if let (true, Some(max)) = (desc.is_capped(), &desc.max_metric) {
+                term += format!(
+                    "<span class=\"note\" title=\"at or beyond the maximum metric\">{}</span>",
+                    max.limit_html()
+                ).as_str();
+            }
+
+            if let Some(sequence) = desc.sequence {
+                term += sequence_html(sequence).as_str();
+            }
+
+            match &desc.note {
+                Some(note) if note.ignores_term() => result += format!(
+                    "<span class=\"note ignored\" title=\"{}\">{}<sup>{}</sup></span>",
+                    note.title(),
+                    term,
+                    note.abbreviation()
+                ).as_str(),
+                _ => result += term.as_str()
             }
         }
 
@@ -185,6 +292,9 @@ pub trait DistanceCalculationLine
 
     fn add(&mut self, tuple: DistanceCalculationTuple<W,R>);
 
+    fn get_max_metric(&self) -> Option<W>;
+
+    #[allow(clippy::too_many_arguments)]
     fn add_indirect(
         &mut self,
         direct_target: usize,
@@ -193,6 +303,7 @@ pub trait DistanceCalculationLine
         distance_target: usize,
         distance_src: usize,
         distance_cost: Cost<W>,
+        note: Option<TermNote>,
     ) {
         self.add(DistanceCalculationTuple {
             description: vec!(
@@ -204,7 +315,10 @@ pub trait DistanceCalculationLine
                 distance_cost
             ),
             through: direct_target,
-            direct: false
+            direct: false,
+            note,
+            max_metric: self.get_max_metric(),
+            sequence: None
         })
     }
 
@@ -212,27 +326,44 @@ pub trait DistanceCalculationLine
         &mut self,
         direct_target: usize,
         direct_src: usize,
-        direct_cost: W
+        direct_cost: Cost<W>,
+        note: Option<TermNote>
     ) {
         self.add(DistanceCalculationTuple {
             description: vec!(Self::draw_direct(direct_target, direct_src)),
-            result: vec!(Cost::Value(direct_cost)),
+            result: vec!(direct_cost),
             through: direct_target,
-            direct: true
+            direct: true,
+            note,
+            max_metric: self.get_max_metric(),
+            sequence: None
         });
     }
 
+    /// Shows the destination sequence number the last term came with.
+    fn set_sequence(&mut self, sequence: u32);
+
     fn min_vector(&self) -> DVValue<W>;
     fn min_cost(&self) -> Cost<W>;
+
+    /// Like `min_vector`, but keeps every next hop that ties for the minimum.
+    fn min_multipath(&self) -> DVValue<W>;
+
+    /// The neighbors whose terms tie for a finite minimum, in the order they were added.
+    fn tied(&self) -> Vec<usize>;
+
+    /// The minimum through `through`, which has to be one of the tied neighbors.
+    fn min_vector_through(&self, through: usize) -> DVValue<W>;
 }
 
-pub struct HtmlFormula<W: Ord + Clone + Add<Output=W> + Display>{
+pub struct HtmlFormula<W: Metric>{
     target: usize,
     source: usize,
-    members: Vec<DistanceCalculationTuple<W, DistanceCalculationElement>>
+    members: Vec<DistanceCalculationTuple<W, DistanceCalculationElement>>,
+    max_metric: Option<W>
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> DistanceCalculationLine<W, DistanceCalculationElement> for HtmlFormula<W>{
+impl<W: Metric> DistanceCalculationLine<W, DistanceCalculationElement> for HtmlFormula<W>{
     fn draw_direct(target: usize, src: usize) -> DistanceCalculationElement {
         DistanceCalculationElement::DirectDistance(target, src)
     }
@@ -249,7 +380,7 @@ impl<W: Ord + Clone + Add<Output=W> + Display> DistanceCalculationLine<W, Distan
         self.source
     }
 
-    fn get_members(&self) -> Iter<DistanceCalculationTuple<W, DistanceCalculationElement>> {
+    fn get_members(&self) -> Iter<'_, DistanceCalculationTuple<W, DistanceCalculationElement>> {
         self.members.iter()
     }
 
@@ -257,73 +388,161 @@ impl<W: Ord + Clone + Add<Output=W> + Display> DistanceCalculationLine<W, Distan
         self.members.push(tuple)
     }
 
+    fn get_max_metric(&self) -> Option<W> {
+        self.max_metric.clone()
+    }
+
+    fn set_sequence(&mut self, sequence: u32) {
+        if let Some(member) = self.members.last_mut() {
+            member.sequence = Some(sequence);
+        }
+    }
+
     fn min_vector(&self) -> DVValue<W> {
         self.members
             .iter()
+            .filter(|m| !m.is_ignored())
             .min()
-            .unwrap()
-            .sum_dv()
+            .map(|m| m.sum_dv())
+            .unwrap_or(DVValue::Infinity)
     }
 
     fn min_cost(&self) -> Cost<W> {
         self.members
             .iter()
+            .filter(|m| !m.is_ignored())
             .min()
-            .unwrap()
-            .sum()
+            .map(|m| m.sum())
+            .unwrap_or(Cost::Infinity)
+    }
+
+    fn min_multipath(&self) -> DVValue<W> {
+        let mut hops = self.tied();
+        hops.sort();
+
+        match self.min_cost() {
+            Cost::Value(w) if hops.len() > 1 => DVValue::Multipath(w, hops),
+            _ => self.min_vector()
+        }
+    }
+
+    fn tied(&self) -> Vec<usize> {
+        let cost = self.min_cost();
+
+        if cost == Cost::Infinity {
+            return Vec::new();
+        }
+
+        self.members
+            .iter()
+            .filter(|m| !m.is_ignored() && m.sum() == cost)
+            .map(|m| m.through)
+            .collect()
+    }
+
+    fn min_vector_through(&self, through: usize) -> DVValue<W> {
+        self.members
+            .iter()
+            .find(|m| !m.is_ignored() && m.through == through)
+            .map(|m| m.sum_dv())
+            .unwrap_or(DVValue::Infinity)
     }
 }
 
-impl<W: Ord + Clone + Add<Output=W> + Display> HtmlFormula<W>{
-    pub fn new(target:usize, source:usize) -> Self {
+impl<W: Metric> HtmlFormula<W>{
+    pub fn new(target:usize, source:usize, max_metric: Option<W>) -> Self {
         HtmlFormula{
             target,
             source,
-            members: Vec::new()
+            members: Vec::new(),
+            max_metric
         }
     }
 }
 
-/*pub struct HtmlFile{
-    internal: Box<dyn Write>
-}
+/// Chrome and Chromium binaries looked up on PATH when no browser is given.
+const CHROME_NAMES: &[&str] = &["google-chrome", "google-chrome-stable", "chromium", "chromium-browser", "chrome"];
+
+/// Where macOS keeps Chrome, it usually isn't on PATH there.
+const MACOS_CHROME: &str = "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome";
 
-impl Write for HtmlFile {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.internal.write(buf)
+/// The chrome binary for pdf output: `chrome` when it's given, otherwise the first
+/// Chrome or Chromium on PATH.
+pub fn find_chrome(chrome: Option<&str>) -> Result<String, Box<dyn Error>> {
+    if let Some(chrome) = chrome {
+        return Ok(chrome.to_owned());
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.internal.flush()
+    let paths = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&paths) {
+        for name in CHROME_NAMES {
+            let candidate = dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX));
+            if candidate.is_file() {
+                return Ok(candidate.display().to_string());
+            }
+        }
     }
-}
 
-impl Drop for HtmlFile {
-    fn drop(&mut self) {
-        writeln!(self.internal, "</div>\n</body>\n</html>").unwrap();
+    if Path::new(MACOS_CHROME).is_file() {
+        return Ok(MACOS_CHROME.to_owned());
     }
+
+    Err(format!("can't find chrome for pdf output, none of {} is on PATH, pass --chrome", CHROME_NAMES.join(", ")).into())
 }
 
+const STYLES: &str = include_str!("../styles.css");
 
-impl HtmlFile {
-    pub fn new<P:AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let mut file = File::create(path)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Html,
+    Pdf,
+}
 
-        writeln!(file, "<!DOCTYPE html>")?;
-        writeln!(file, "<html>\n<head>")?;
-        writeln!(file, "<link rel=\"stylesheet\" href=\"styles.css\">")?;
+impl FromStr for OutputFormat {
+    type Err = String;
 
-        writeln!(file, "</head>\n<body>")?;
-        writeln!(file, "<div class=\"wrapper\">")?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(OutputFormat::Html),
+            "pdf" => Ok(OutputFormat::Pdf),
+            _ => Err(format!("unknown output format {}, expected html or pdf", s))
+        }
+    }
+}
 
-        Ok(HtmlFile{ internal: Box::new(file) })
+/// Prints an html page in `folder` to a pdf with the same stem using headless chrome.
+pub fn render_pdf(chrome: &str, folder: &Path, file_name: &str) -> Result<(), Box<dyn Error>> {
+    let pdf_file_name = Path::new(file_name).with_extension("pdf");
+
+    let output = Command::new(chrome)
+        .current_dir(folder)
+        .arg("--headless")
+        .arg(format!("--print-to-pdf={}", pdf_file_name.display()))
+        .arg("--disable-gpu")
+        .arg("--no-margins")
+        .arg("--print-to-pdf-no-header")
+        .arg(file_name)
+        .output()
+        .map_err(|e| format!("can't run {}: {}", chrome, e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed rendering {}: {}",
+            chrome,
+            file_name,
+            String::from_utf8_lossy(&output.stderr)
+        ).into());
     }
-}*/
+
+    Ok(())
+}
 
 pub struct HtmlFiles {
     folder: String,
     prefix: String,
-    index: u32
+    index: u32,
+    formats: Vec<OutputFormat>,
+    chrome: Option<String>
 }
 
 impl HtmlFiles {
@@ -331,26 +550,37 @@ impl HtmlFiles {
         HtmlFiles{
             folder: path.to_string(),
             prefix: prefix.to_string(),
-            index: 0
+            index: 0,
+            formats: vec!(OutputFormat::Html),
+            chrome: None
         }
     }
 
+    pub fn with_formats(mut self, formats: &[OutputFormat]) -> Self {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Uses `chrome` for pdf output, `None` looks it up on PATH.
+    pub fn with_chrome(mut self, chrome: Option<&str>) -> Self {
+        self.chrome = chrome.map(|c| c.to_string());
+        self
+    }
+
     pub fn create<F>(&mut self, cb: F) -> Result<(), Box<dyn Error>>
         where F : Fn(&mut File) -> Result<(), Box<dyn Error>>  {
 
         let path = Path::new(self.folder.as_str());
-        let styles_filename = Path::new("styles.css");
 
         if self.index == 0 {
             if !path.exists() {
                 fs::create_dir_all(path)?;
             }
 
-            fs::copy(styles_filename, path.join(styles_filename))?;
+            fs::write(path.join("styles.css"), STYLES)?;
         }
 
         let file_name = format!("{}_{}.html", self.prefix.as_str(), self.index);
-        let pdf_file_name = format!("{}_{}.pdf", self.prefix.as_str(), self.index);
         self.index += 1;
 
         {
@@ -367,16 +597,13 @@ impl HtmlFiles {
             writeln!(file, "</div>\n</body>\n</html>")?;
         }
 
-        Command::new("/Applications/Google Chrome.app/Contents/MacOS/Google Chrome")
-            .current_dir(path)
-            .arg("--headless")
-            .arg(format!("--print-to-pdf={}",pdf_file_name.as_str()))
-            .arg("--disable-gpu")
-            .arg("--no-margins")
-            .arg("--print-to-pdf-no-header")
-            .arg(file_name.as_str())
-            .output()?;
+        if self.formats.contains(&OutputFormat::Pdf) {
+            render_pdf(find_chrome(self.chrome.as_deref())?.as_str(), path, file_name.as_str())?;
 
+            if !self.formats.contains(&OutputFormat::Html) {
+                fs::remove_file(path.join(file_name.as_str()))?;
+            }
+        }
 
         Ok(())
     }
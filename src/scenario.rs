@@ -0,0 +1,507 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::metric::Metric;
+use crate::options::{Engine, Options, Protocol, TieBreak, UpdateOrder};
+use crate::repr::HtmlFiles;
+use crate::world::events::{run_events, Delays};
+use crate::world::{run_until_stable, Convergence, LinkLoss, Losses, Operation, RecoveryMode, World};
+
+/// A link between two nodes, used for the initial topology.
+/// `weight` is the cost from `a` to `b`, and also from `b` to `a` unless
+/// `reverse_weight` is given. The options' algebra says what the weights mean. `delay` is only used by the event engine.
+/// `loss` and `duplication` are probabilities for each table sent over the link,
+/// a duplicate only makes a difference on the event engine.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkSpec {
+    pub a: String,
+    pub b: String,
+    pub weight: u32,
+    #[serde(default)]
+    pub reverse_weight: Option<u32>,
+    #[serde(default)]
+    pub delay: Option<u32>,
+    #[serde(default)]
+    pub loss: f64,
+    #[serde(default)]
+    pub duplication: f64,
+}
+
+/// A single operation inside a step, identified by its `op` field.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OperationSpec {
+    ChangeWeight { a: String, b: String, weight: u32 },
+    ChangeDirectedWeight { a: String, b: String, weight: u32 },
+    RemoveLink { a: String, b: String },
+    NodeDown { node: String },
+    NodeUp {
+        node: String,
+        #[serde(default)]
+        mode: RecoveryMode,
+    },
+    StopUpdates { node: String },
+    ResumeUpdates { node: String },
+}
+
+/// A packet sent from `from` to `to` once the simulation reaches generation `at`,
+/// or after it settles when `at` isn't given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PacketSpec {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub at: Option<u32>,
+}
+
+/// A batch of operations applied together once the previous state is stable.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    #[serde(default)]
+    pub operations: Vec<OperationSpec>,
+}
+
+/// A scenario as stored in a TOML or JSON file:
+///
+/// ```toml
+/// nodes = ["A", "B", "C"]
+/// links = [
+///     { a = "A", b = "B", weight = 2 },
+///     { a = "B", b = "C", weight = 7, delay = 3, loss = 0.2 },
+/// ]
+///
+/// [options]
+/// protocol = "distance_vector"
+/// engine = "events"
+/// update_order = "sequential"
+/// order = ["C", "A"]
+/// horizon = "split_horizon"
+/// algebra = "shortest_path"
+/// max_metric = 16
+/// timers = { timeout = 6, garbage_collection = 4 }
+/// hold_down = 3
+/// seed = 42
+/// ecmp = true
+/// tie_break = "keep_current"
+///
+/// [[packets]]
+/// from = "A"
+/// to = "C"
+/// at = 4
+///
+/// [[steps]]
+/// operations = [
+///     { op = "change_weight", a = "B", b = "C", weight = 80 },
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    pub nodes: Vec<String>,
+    #[serde(default)]
+    pub links: Vec<LinkSpec>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub options: Options<u32>,
+    #[serde(default)]
+    pub packets: Vec<PacketSpec>,
+}
+
+impl OperationSpec {
+    fn to_operation<W: Metric>(&self, world: &World<W>) -> Result<Operation<W>, Box<dyn Error>> {
+        match self {
+            OperationSpec::ChangeWeight { a, b, weight } =>
+                world.add_interface(a, b, W::from_weight(*weight)?),
+            OperationSpec::ChangeDirectedWeight { a, b, weight } =>
+                world.set_directed_weight(a, b, W::from_weight(*weight)?),
+            OperationSpec::RemoveLink { a, b } =>
+                world.remove_interface(a, b),
+            OperationSpec::NodeDown { node } =>
+                world.node_down(node),
+            OperationSpec::NodeUp { node, mode } =>
+                world.node_up(node, *mode),
+            OperationSpec::StopUpdates { node } =>
+                world.stop_updates(node),
+            OperationSpec::ResumeUpdates { node } =>
+                world.resume_updates(node)
+        }
+    }
+}
+
+impl Scenario {
+    /// Loads a scenario, picking the parser by extension (`.json` or TOML otherwise).
+    /// When the file doesn't set a name the file stem is used.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+
+        let mut scenario: Scenario = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(content.as_str())
+                .map_err(|e| format!("can't parse {}: {}", path.display(), e))?,
+            _ => toml::from_str(content.as_str())
+                .map_err(|e| format!("can't parse {}: {}", path.display(), e))?,
+        };
+
+        if scenario.name.is_empty() {
+            scenario.name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("scenario")
+                .to_owned();
+        }
+
+        scenario.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(scenario)
+    }
+
+    /// Checks that the node names are unique and that every link joins two different
+    /// nodes from the list.
+    fn validate(&self) -> Result<(), String> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if self.nodes[..index].contains(node) {
+                return Err(format!("node {} is listed twice", node));
+            }
+        }
+
+        for link in &self.links {
+            for node in [&link.a, &link.b] {
+                if !self.nodes.contains(node) {
+                    return Err(format!("the link between {} and {} has the unknown node {}", link.a, link.b, node));
+                }
+            }
+
+            if link.a == link.b {
+                return Err(format!("the link from {} goes back to {}", link.a, link.b));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The link delays for the event engine, in both directions of every link.
+    fn delays(&self) -> Result<Delays, Box<dyn Error>> {
+        let mut delays = Delays::new();
+        let index = |name: &String| self.nodes.iter().position(|n| n == name);
+
+        for link in &self.links {
+            if let (Some(a), Some(b), Some(delay)) = (index(&link.a), index(&link.b), link.delay) {
+                if delay == 0 {
+                    return Err(format!("the link between {} and {} needs a delay of at least 1", link.a, link.b).into());
+                }
+
+                delays.insert((a, b), delay);
+                delays.insert((b, a), delay);
+            }
+        }
+
+        Ok(delays)
+    }
+
+    /// Whether the run depends on the seed, because links lose or duplicate tables,
+    /// or the update order or tie-breaking is random.
+    pub fn uses_seed(&self) -> bool {
+        self.options.update_order == UpdateOrder::Random ||
+            self.options.tie_break == TieBreak::Random ||
+            self.links.iter().any(|l| l.loss > 0.0 || l.duplication > 0.0)
+    }
+
+    /// The unreliable links, in both directions.
+    fn losses(&self) -> Result<Losses, Box<dyn Error>> {
+        let mut losses = Losses::new();
+        let index = |name: &String| self.nodes.iter().position(|n| n == name);
+
+        for link in &self.links {
+            for p in [link.loss, link.duplication] {
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(format!(
+                        "the link between {} and {} has a probability of {}, it has to be between 0 and 1",
+                        link.a,
+                        link.b,
+                        p
+                    ).into());
+                }
+            }
+
+            if let (Some(a), Some(b), true) = (index(&link.a), index(&link.b), link.loss > 0.0 || link.duplication > 0.0) {
+                let loss = LinkLoss { loss: link.loss, duplication: link.duplication };
+
+                losses.insert((a, b), loss);
+                losses.insert((b, a), loss);
+            }
+        }
+
+        Ok(losses)
+    }
+
+    /// Applies `operations` and runs the result on the configured engine until nothing
+    /// changes anymore, or it's clear it won't. The run is recorded in the world.
+    fn settle<W: Metric>(
+        &self,
+        html_factory: &mut HtmlFiles,
+        world: &World<W>,
+        operations: Vec<Operation<W>>,
+        delays: &Delays
+    ) -> Result<(World<W>, Convergence), Box<dyn Error>> {
+        let (started, messages) = (world.generation(), world.messages());
+        let next = world.apply_operations(html_factory, operations)?;
+
+        let (mut stable, convergence) = match self.options.engine {
+            Engine::Lockstep => run_until_stable(html_factory, next)?,
+            Engine::Events => run_events(html_factory, next, delays)?
+        };
+        stable.record_run(started, messages, convergence);
+
+        Ok((stable, convergence))
+    }
+
+    /// Builds the initial world, runs it until stable and then applies every step
+    /// in turn, running until stable after each one. When a run doesn't converge the
+    /// remaining steps are skipped, `unsettled` says which. Packets still on the way at the end go through
+    /// the final tables. `W` has to be the metric of the options' algebra.
+    pub fn run<W: Metric>(&self, html_factory: &mut HtmlFiles) -> Result<(World<W>, Convergence), Box<dyn Error>> {
+        if self.options.ecmp && self.options.protocol != Protocol::DistanceVector {
+            return Err(format!("equal-cost multipath only works with distance vector, not {}", self.options.protocol).into());
+        }
+
+        self.validate()?;
+
+        let delays = self.delays()?;
+        let mut world: World<W> = World::new(self.nodes.iter().map(|n| n.as_str()).collect())
+            .with_options(self.options.clone().map_max_metric(W::from_max_metric)?)
+            .with_losses(self.losses()?);
+
+        for packet in &self.packets {
+            world.inject(&packet.from, &packet.to, packet.at.unwrap_or(u32::MAX))?;
+        }
+
+        let mut operations = Vec::new();
+        for link in &self.links {
+            operations.push(world.add_interface(&link.a, &link.b, W::from_weight(link.weight)?)?);
+
+            if let Some(reverse_weight) = link.reverse_weight {
+                operations.push(world.set_directed_weight(&link.b, &link.a, W::from_weight(reverse_weight)?)?);
+            }
+        }
+
+        let (mut stable, mut convergence) = self.settle(html_factory, &world, operations, &delays)?;
+
+        for step in &self.steps {
+            if convergence != Convergence::Converged {
+                break;
+            }
+
+            let mut operations = Vec::new();
+            for op in &step.operations {
+                operations.push(op.to_operation(&stable)?);
+            }
+
+            (stable, convergence) = self.settle(html_factory, &stable, operations, &delays)?;
+        }
+
+        stable.finish_packets();
+
+        Ok((stable, convergence))
+    }
+
+    /// Which run of `world` didn't settle and how many steps were left out after it, None
+    /// when every run settled.
+    pub fn unsettled<W: Metric>(&self, world: &World<W>, convergence: Convergence) -> Option<String> {
+        if convergence == Convergence::Converged {
+            return None;
+        }
+
+        let step = world.runs().len().saturating_sub(1);
+        let skipped = self.steps.len().saturating_sub(step);
+        let run = if step == 0 {
+            String::from("the initial topology")
+        } else {
+            format!("step {} of {}", step, self.steps.len())
+        };
+
+        Some(match skipped {
+            0 => format!("{} {}", run, convergence),
+            1 => format!("{} {}, skipped the last step", run, convergence),
+            _ => format!("{} {}, skipped the {} steps after it", run, convergence, skipped)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::metric::Reliability;
+    use crate::world::tests::run_scenario;
+
+    const SCENARIO: &str = r#"
+        nodes = ["A", "B", "C"]
+        links = [
+            { a = "A", b = "B", weight = 2 },
+            { a = "B", b = "C", weight = 7, reverse_weight = 3, delay = 2, loss = 0.5 },
+        ]
+
+        [[steps]]
+        operations = [
+            { op = "change_weight", a = "A", b = "C", weight = 4 },
+            { op = "change_directed_weight", a = "C", b = "A", weight = 5 },
+            { op = "remove_link", a = "A", b = "B" },
+            { op = "node_down", node = "B" },
+            { op = "node_up", node = "B", mode = "keep" },
+            { op = "node_up", node = "C" },
+            { op = "stop_updates", node = "C" },
+            { op = "resume_updates", node = "C" },
+        ]
+    "#;
+
+    fn world<W: Metric>() -> World<W> {
+        World::new(vec!("A", "B", "C"))
+    }
+
+    #[test]
+    fn parses_every_operation() {
+        let scenario: Scenario = toml::from_str(SCENARIO).unwrap();
+
+        assert_eq!(scenario.links[1].reverse_weight, Some(3));
+        assert_eq!(scenario.links[1].delay, Some(2));
+        assert_eq!(scenario.links[1].loss, 0.5);
+        assert!(scenario.uses_seed());
+
+        let operations = &scenario.steps[0].operations;
+        assert!(matches!(&operations[0], OperationSpec::ChangeWeight { a, b, weight: 4 } if a == "A" && b == "C"));
+        assert!(matches!(&operations[1], OperationSpec::ChangeDirectedWeight { a, b, weight: 5 } if a == "C" && b == "A"));
+        assert!(matches!(&operations[2], OperationSpec::RemoveLink { a, b } if a == "A" && b == "B"));
+        assert!(matches!(&operations[3], OperationSpec::NodeDown { node } if node == "B"));
+        assert!(matches!(&operations[4], OperationSpec::NodeUp { node, mode: RecoveryMode::Keep } if node == "B"));
+        assert!(matches!(&operations[5], OperationSpec::NodeUp { mode: RecoveryMode::Blank, .. }));
+        assert!(matches!(&operations[6], OperationSpec::StopUpdates { node } if node == "C"));
+        assert!(matches!(&operations[7], OperationSpec::ResumeUpdates { node } if node == "C"));
+
+        let world = world::<u32>();
+        assert!(matches!(operations[0].to_operation(&world), Ok(Operation::ChangeWeight(0, 2, 4))));
+        assert!(matches!(operations[1].to_operation(&world), Ok(Operation::ChangeDirectedWeight(2, 0, 5))));
+        assert!(matches!(operations[3].to_operation(&world), Ok(Operation::NodeDown(1))));
+        assert!(matches!(operations[6].to_operation(&world), Ok(Operation::StopUpdates(2))));
+    }
+
+    #[test]
+    fn rejects_an_unknown_operation() {
+        let error = toml::from_str::<Scenario>(r#"
+            nodes = ["A", "B"]
+
+            [[steps]]
+            operations = [{ op = "flap_link", a = "A", b = "B" }]
+        "#).unwrap_err();
+
+        assert!(error.to_string().contains("flap_link"));
+    }
+
+    #[test]
+    fn rejects_an_unknown_node() {
+        let op = OperationSpec::ChangeWeight { a: String::from("A"), b: String::from("X"), weight: 1 };
+        let error = op.to_operation(&world::<u32>()).unwrap_err();
+
+        assert_eq!(error.to_string(), "can't find node X");
+    }
+
+    #[test]
+    fn rejects_a_weight_the_algebra_does_not_allow() {
+        let op = OperationSpec::ChangeWeight { a: String::from("A"), b: String::from("B"), weight: 150 };
+
+        assert!(op.to_operation(&world::<u32>()).is_ok());
+        let error = op.to_operation(&world::<Reliability>()).unwrap_err();
+        assert_eq!(error.to_string(), "a reliability of 150% is more than 100%");
+    }
+
+    #[test]
+    fn rejects_a_probability_above_one() {
+        let scenario: Scenario = toml::from_str(r#"
+            nodes = ["A", "B"]
+            links = [{ a = "A", b = "B", weight = 1, loss = 1.5 }]
+        "#).unwrap();
+
+        assert!(scenario.losses().is_err());
+    }
+
+    #[test]
+    fn says_which_step_did_not_settle_and_what_was_skipped() {
+        let toml = |max_generations: u32| format!(r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+            ]
+
+            [options]
+            max_generations = {}
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "A", b = "B", weight = 2 }}]
+
+            [[steps]]
+            operations = [{{ op = "remove_link", a = "B", b = "C" }}]
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "A", b = "B", weight = 3 }}]
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "A", b = "B", weight = 4 }}]
+        "#, max_generations);
+
+        // Without a maximum metric A and B count up until the limit after B-C is gone
+        let scenario: Scenario = toml::from_str(toml(20).as_str()).unwrap();
+        let (world, convergence) = run_scenario("unsettled_step", toml(20).as_str());
+        assert_eq!(convergence, Convergence::GenerationLimit);
+        assert_eq!(
+            scenario.unsettled(&world, convergence).as_deref(),
+            Some("step 2 of 4 hit the generation limit, skipped the 2 steps after it")
+        );
+
+        let (world, convergence) = run_scenario("unsettled_initial", toml(1).as_str());
+        assert_eq!(
+            scenario.unsettled(&world, convergence).as_deref(),
+            Some("the initial topology hit the generation limit, skipped the 4 steps after it")
+        );
+
+        let settled: Scenario = toml::from_str(r#"nodes = ["A"]"#).unwrap();
+        let (world, convergence) = run_scenario("unsettled_none", r#"nodes = ["A"]"#);
+        assert_eq!(settled.unsettled(&world, convergence), None);
+    }
+
+    #[test]
+    fn rejects_duplicate_nodes_self_links_and_unknown_nodes() {
+        let validate = |toml: &str| toml::from_str::<Scenario>(toml).unwrap().validate();
+
+        assert_eq!(validate(r#"nodes = ["A", "B", "A"]"#), Err(String::from("node A is listed twice")));
+        assert_eq!(
+            validate(r#"
+                nodes = ["A", "B"]
+                links = [{ a = "A", b = "A", weight = 1 }]
+            "#),
+            Err(String::from("the link from A goes back to A"))
+        );
+        assert_eq!(
+            validate(r#"
+                nodes = ["A", "B"]
+                links = [{ a = "A", b = "X", weight = 1, delay = 2 }]
+            "#),
+            Err(String::from("the link between A and X has the unknown node X"))
+        );
+        assert_eq!(validate(SCENARIO), Ok(()));
+    }
+
+    #[test]
+    fn load_rejects_an_invalid_scenario() {
+        let path = env::temp_dir().join("dvr_sim_tests_duplicate.toml");
+        fs::write(&path, r#"nodes = ["A", "A"]"#).unwrap();
+
+        let error = Scenario::load(&path).unwrap_err();
+        assert_eq!(error.to_string(), format!("{}: node A is listed twice", path.display()));
+    }
+}
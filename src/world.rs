@@ -0,0 +1,2182 @@
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use crate::cost::{same_routes, Cost, DVValue};
+use crate::metric::Metric;
+use crate::options::{HorizonMode, Options, Protocol, TieBreak, Timers, UpdateOrder};
+use crate::random::Random;
+use crate::repr::{HtmlFormula, DistanceCalculationLine, HtmlFiles, TermNote};
+use serde::Deserialize;
+
+pub mod analysis;
+pub mod dual;
+pub mod events;
+pub mod link_state;
+pub mod oracle;
+pub mod trace;
+
+use analysis::{warning_banner, CountToInfinity, ForwardingLoop};
+use dual::Dual;
+use events::Delays;
+use link_state::Lsdb;
+use oracle::RouteCheck;
+use trace::Packet;
+
+#[derive(Debug, Clone)]
+pub struct Neighbor<W: Metric> {
+    index: usize,
+    direct_cost: W,
+    dv: Vec<DVValue<W>>,
+    // Rounds since this neighbor last advertised its table
+    age: u32
+}
+
+/// Per destination timer state, only used when route timers are enabled.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RouteTimer {
+    /// No route to time out
+    Idle,
+    /// Rounds since the next hop last refreshed the route
+    Age(u32),
+    /// Timed out through the given next hop, removed when the rounds run out
+    GarbageCollection(usize, u32),
+}
+
+/// A destination whose route recently got worse, only its old next hop is listened to
+/// until the rounds run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HoldDown {
+    via: usize,
+    rounds: u32
+}
+
+/// Directed relations with their weights, as kept by `copy_relations`
+type Links<W> = Vec<((usize, usize), W)>;
+
+/// How often a table sent over a directed link goes missing or arrives twice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkLoss {
+    pub loss: f64,
+    pub duplication: f64
+}
+
+/// Unreliable directed links, keyed by (sender, receiver)
+pub type Losses = HashMap<(usize, usize), LinkLoss>;
+
+#[derive(Debug, Clone)]
+pub struct Node<W: Metric> {
+    name: String,
+    dv: Vec<DVValue<W>>,
+    neighbors: Vec<Neighbor<W>>,
+    index: usize,
+    has_updates: bool,
+    // The links a failed node had, restored when it comes back up
+    down_links: Option<Links<W>>,
+    // A silent node still computes but stops advertising to its neighbors
+    silent: bool,
+    timers: Vec<RouteTimer>,
+    hold_down: Vec<Option<HoldDown>>,
+    // Only used by the link state protocol
+    lsdb: Lsdb<W>,
+    // Only used by DUAL
+    dual: Dual<W>
+}
+
+/// What a node remembers when it comes back up after a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryMode {
+    /// Restart with every destination at infinity
+    #[default]
+    Blank,
+    /// Restart with the table the node had when it went down
+    Keep,
+}
+
+#[derive(Debug)]
+pub enum Operation<W: Metric> {
+    ChangeWeight(usize, usize, W),
+    // Only changes the cost of sending from the first node to the second
+    ChangeDirectedWeight(usize, usize, W),
+    RemoveLink(usize, usize),
+    NodeDown(usize),
+    NodeUp(usize, RecoveryMode),
+    StopUpdates(usize),
+    ResumeUpdates(usize)
+}
+
+#[derive(Debug, Clone)]
+pub struct World<W: Metric> {
+    nodes: Vec<Node<W>>,
+    generation: u32,
+    options: Options<W>,
+    losses: Losses,
+    // Decides which tables get lost and the random update order, seeded from the options
+    random: Random,
+    // Every count to infinity seen so far, including the ones still going on
+    counting: Vec<CountToInfinity>,
+    // (generation, number of forwarding loops) for every generation that had loops
+    loop_counts: Vec<(u32, usize)>,
+    // Packets that follow the routes as they change
+    packets: Vec<Packet<W>>,
+    // The tables of every converged run compared with the shortest paths
+    route_checks: Vec<RouteCheck<W>>,
+    // Tables or LSAs sent over a link so far
+    messages: u32,
+    runs: Vec<RunStats>
+}
+
+/// The outcome of recomputing a single node.
+struct Computed<W: Metric> {
+    dv: Vec<DVValue<W>>,
+    timers: Vec<RouteTimer>,
+    hold_down: Vec<Option<HoldDown>>,
+    // The formula behind every entry, for printing
+    lines: Vec<String>
+}
+
+/// How a run of the simulation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convergence {
+    /// Nothing changes anymore
+    Converged,
+    /// Stopped at `max_generations` while still changing
+    GenerationLimit,
+    /// The whole state came back after this many generations. With the same random generator
+    /// it will keep doing that, otherwise the run went on until the generation limit
+    Oscillating(u32),
+}
+
+/// How long a run took from the operations that started it, and how many messages it needed.
+#[derive(Debug, Clone, Copy)]
+pub struct RunStats {
+    started: u32,
+    ended: u32,
+    messages: u32,
+    convergence: Convergence
+}
+
+impl RunStats {
+    pub fn rounds(&self) -> u32 {
+        self.ended - self.started
+    }
+
+    pub fn messages(&self) -> u32 {
+        self.messages
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "t={} to t={}: {} after {} round{}, {} message{}",
+            self.started,
+            self.ended,
+            self.convergence,
+            self.rounds(),
+            if self.rounds() == 1 { "" } else { "s" },
+            self.messages,
+            if self.messages == 1 { "" } else { "s" }
+        )
+    }
+}
+
+impl Display for Convergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Convergence::Converged => write!(f, "stable"),
+            Convergence::GenerationLimit => write!(f, "hit the generation limit"),
+            Convergence::Oscillating(period) => write!(f, "oscillating with period {}", period)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NewState<W: Metric> {
+    Changed(Box<World<W>>),
+    NotChanged,
+}
+
+impl<W: Metric> Node<W> {
+    fn is_down(&self) -> bool {
+        self.down_links.is_some()
+    }
+
+    fn neighbor(&self, index: usize) -> Option<&Neighbor<W>> {
+        self.neighbors.iter().find(|n| n.index == index)
+    }
+}
+
+fn modify_dv<W: Metric>(
+    original: &[DVValue<W>],
+    node_b: usize,
+    new_w: W,
+    max_metric: Option<&W>,
+) -> Vec<DVValue<W>> {
+    let mut new_dv = Vec::new();
+
+    for (index, v) in original.iter().enumerate() {
+        let cost = Cost::Value(new_w.to_owned()).capped(max_metric);
+
+        if let (true, DVValue::Dsdv(_, _, sequence)) = (index == node_b, v) {
+            new_dv.push(DVValue::Dsdv(cost, node_b, *sequence));
+        } else if index == node_b {
+            new_dv.push(cost.to_dv_value(node_b, false));
+        } else {
+            new_dv.push(v.clone());
+        }
+    }
+
+    new_dv
+}
+
+/// Drops every route that went out through `node_b`, including the direct route to it.
+/// Multipath routes keep their other next hops.
+fn invalidate_via<W: Metric>(
+    original: &[DVValue<W>],
+    node_b: usize,
+) -> Vec<DVValue<W>> {
+    let mut new_dv = Vec::new();
+
+    for (index, v) in original.iter().enumerate() {
+        match v {
+            DVValue::Distance(_, via) if *via == node_b => new_dv.push(DVValue::Infinity),
+            DVValue::Path(_, path) if path.first() == Some(&node_b) => new_dv.push(DVValue::Infinity),
+            DVValue::Dsdv(_, via, _) if *via == node_b => new_dv.push(v.broken()),
+            DVValue::Multipath(w, hops) if hops.contains(&node_b) => {
+                let rest: Vec<usize> = hops.iter().copied().filter(|h| *h != node_b).collect();
+
+                new_dv.push(match rest.as_slice() {
+                    [via] => Cost::Value(w.to_owned()).to_dv_value(*via, *via == index),
+                    _ => DVValue::Multipath(w.to_owned(), rest)
+                });
+            }
+            _ if index == node_b => new_dv.push(v.broken()),
+            _ => new_dv.push(v.clone())
+        }
+    }
+
+    new_dv
+}
+
+/// The even sequence number a DSDV node announces itself with after `sequence`.
+fn next_sequence(sequence: u32) -> u32 {
+    (sequence | 1) + 1
+}
+
+
+impl<W: Metric> World<W> {
+    pub fn new(node_names: Vec<&str>) -> World<W> {
+        let size = node_names.len();
+
+        let mut nodes: Vec<Node<W>> = Vec::with_capacity(size);
+
+        for (index, name) in node_names.into_iter().enumerate() {
+            let mut dv_vector: Vec<DVValue<W>> = Vec::with_capacity(size);
+
+            for _ in 0 .. size {
+                dv_vector.push(DVValue::Infinity)
+            }
+
+            nodes.push(Node{
+                name: name.to_owned(),
+                dv: dv_vector,
+                neighbors: Vec::new(),
+                index,
+                has_updates: false,
+                down_links: None,
+                silent: false,
+                timers: vec!(RouteTimer::Idle; size),
+                hold_down: vec!(None; size),
+                lsdb: Lsdb::default(),
+                dual: Dual::default()
+            });
+        }
+
+        World {
+            nodes,
+            generation: 0,
+            options: Options::default(),
+            losses: Losses::new(),
+            random: Random::new(0),
+            counting: Vec::new(),
+            loop_counts: Vec::new(),
+            packets: Vec::new(),
+            route_checks: Vec::new(),
+            messages: 0,
+            runs: Vec::new()
+        }
+    }
+
+    pub fn with_options(mut self, options: Options<W>) -> Self {
+        self.random = Random::new(options.seed.unwrap_or(0));
+        self.options = options;
+        self
+    }
+
+    pub fn with_losses(mut self, losses: Losses) -> Self {
+        self.losses = losses;
+        self
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Tables or LSAs sent over a link so far.
+    pub fn messages(&self) -> u32 {
+        self.messages
+    }
+
+    /// Every run so far, from the operations that started it until it settled.
+    pub fn runs(&self) -> &[RunStats] {
+        &self.runs
+    }
+
+    /// Records a run that started at `started`, when `messages` had been sent.
+    pub fn record_run(&mut self, started: u32, messages: u32, convergence: Convergence) {
+        self.runs.push(RunStats {
+            started,
+            ended: self.generation,
+            messages: self.messages - messages,
+            convergence
+        });
+    }
+
+    fn find_node(&self, name: &str) -> Option<&Node<W>> {
+        self.nodes
+            .iter()
+            .find(|n| n.name == name)
+    }
+
+    pub fn node_names(&self) -> BTreeMap<usize, String> {
+        let mut names:BTreeMap<usize, String> = BTreeMap::new();
+
+        for sub_node in &self.nodes {
+            names.insert(sub_node.index, sub_node.name.to_owned());
+        }
+
+        names
+    }
+
+    fn node_index(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        Ok(self.find_node(name)
+            .map(|n| n.index)
+            .ok_or_else(|| format!("can't find node {}", name))?)
+    }
+
+    fn node_index_up(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        let index = self.node_index(name)?;
+
+        if self.nodes[index].is_down() {
+            return Err(format!("node {} is down", name).into());
+        }
+
+        Ok(index)
+    }
+
+    pub fn add_interface(&self, node_a: &str, node_b: &str, weight: W) -> Result<Operation<W>, Box<dyn Error>> {
+        Ok(Operation::ChangeWeight(
+            self.node_index_up(node_a)?,
+            self.node_index_up(node_b)?,
+            weight,
+        ))
+    }
+
+    pub fn node_down(&self, name: &str) -> Result<Operation<W>, Box<dyn Error>> {
+        Ok(Operation::NodeDown(self.node_index_up(name)?))
+    }
+
+    pub fn node_up(&self, name: &str, mode: RecoveryMode) -> Result<Operation<W>, Box<dyn Error>> {
+        let index = self.node_index(name)?;
+
+        if !self.nodes[index].is_down() {
+            return Err(format!("node {} isn't down", name).into());
+        }
+
+        Ok(Operation::NodeUp(index, mode))
+    }
+
+    pub fn set_directed_weight(&self, node_a: &str, node_b: &str, weight: W) -> Result<Operation<W>, Box<dyn Error>> {
+        Ok(Operation::ChangeDirectedWeight(
+            self.node_index_up(node_a)?,
+            self.node_index_up(node_b)?,
+            weight,
+        ))
+    }
+
+    pub fn stop_updates(&self, name: &str) -> Result<Operation<W>, Box<dyn Error>> {
+        Ok(Operation::StopUpdates(self.node_index_up(name)?))
+    }
+
+    pub fn resume_updates(&self, name: &str) -> Result<Operation<W>, Box<dyn Error>> {
+        let index = self.node_index(name)?;
+
+        if !self.nodes[index].silent {
+            return Err(format!("node {} isn't silent", name).into());
+        }
+
+        Ok(Operation::ResumeUpdates(index))
+    }
+
+    pub fn remove_interface(&self, node_a: &str, node_b: &str) -> Result<Operation<W>, Box<dyn Error>> {
+        let index_a = self.node_index(node_a)?;
+        let index_b = self.node_index(node_b)?;
+
+        if !self.nodes[index_a].neighbors.iter().any(|n| n.index == index_b) {
+            return Err(format!("there's no link between {} and {}", node_a, node_b).into());
+        }
+
+        Ok(Operation::RemoveLink(index_a, index_b))
+    }
+
+    /// Opens the table of `node` and prints its header and its routes, `changed` shows
+    /// how they change.
+    fn print_routes<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        changed: Option<&Vec<DVValue<W>>>,
+        timers: &[RouteTimer],
+        loops: &[ForwardingLoop]
+    ) -> Result<(), Box<dyn Error>> {
+        let names = self.node_names();
+        // Entries that send packets around in a loop get marked
+        let cell = |index: usize| match loops.iter().find(|l| l.contains(node.index, index)) {
+            Some(l) => format!("<td class=\"loop\" title=\"{}\">", l.describe(&names)),
+            None => String::from("<td>")
+        };
+        if node.is_down() {
+            writeln!(writer, "<table class=\"down\">\n\t<tr>")?;
+            writeln!(writer, "\t\t<th>{} (down)</th>", node.name)?;
+        } else if node.silent {
+            writeln!(writer, "<table class=\"silent\">\n\t<tr>")?;
+            writeln!(writer, "\t\t<th>{} (silent)</th>", node.name)?;
+        } else {
+            writeln!(writer, "<table>\n\t<tr>")?;
+            writeln!(writer, "\t\t<th>{}</th>", node.name)?;
+        }
+
+        for sub_node in &self.nodes {
+            writeln!(writer, "\t\t<th>{}</th>", sub_node.name)?;
+        }
+
+        writeln!(writer, "\t</tr>\n\t<tr>\n\t\t<th>{}</th>",node.name)?;
+        // If there's a new dv, run the more complex algorithm
+        if let Some(new_dv) = changed {
+            for (index, new_value) in new_dv.iter().enumerate() {
+                if new_value.same_route(node.dv.get(index).unwrap(), index) {
+                    writeln!(
+                        writer,
+                        "\t\t{}{}{}</td>",
+                        cell(index),
+                        new_value.write_html_long(&names),
+                        self.timer_html(&timers[index], &names)
+                    )?;
+                } else {
+                    writeln!(
+                        writer,
+                        "\t\t{}{}&#8594;{}{}</td>",
+                        cell(index),
+                        node.dv.get(index).unwrap().write_html_long(&names),
+                        new_value.write_html_long(&names),
+                        self.timer_html(&timers[index], &names)
+                    )?;
+                }
+            }
+        } else {
+            for (index, new_value) in node.dv.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "\t\t{}{}{}</td>",
+                    cell(index),
+                    new_value.write_html_long(&names),
+                    self.timer_html(&timers[index], &names)
+                )?;
+            }
+        }
+
+        writeln!(writer, "\t</tr>")?;
+
+        Ok(())
+    }
+
+    fn print_node<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        changed: Option<&Vec<DVValue<W>>>,
+        timers: &[RouteTimer],
+        removed: &[(usize, usize)],
+        loops: &[ForwardingLoop]
+    ) -> Result<(), Box<dyn Error>> {
+        let names = self.node_names();
+        self.print_routes(writer, node, changed, timers, loops)?;
+
+        for neighbor in &node.neighbors {
+            let reverse_cost = self.nodes[neighbor.index]
+                .neighbors
+                .iter()
+                .find(|n| n.index == node.index)
+                .map(|n| &n.direct_cost);
+
+            let mut header = names.get(&neighbor.index).unwrap().to_owned();
+
+            match reverse_cost {
+                Some(reverse_cost) if *reverse_cost != neighbor.direct_cost =>
+                    header += format!(" (&#8594;{}, &#8592;{})", neighbor.direct_cost, reverse_cost).as_str(),
+                _ => {}
+            }
+
+            if neighbor.age > 0 && self.nodes[neighbor.index].silent {
+                header += format!(" <span class=\"timer\">silent {}</span>", neighbor.age).as_str();
+            } else if neighbor.age > 0 {
+                header += format!(
+                    " <span class=\"timer\" title=\"tables lost in a row\">missed {}</span>",
+                    neighbor.age
+                ).as_str();
+            }
+
+            writeln!(writer, "\t<tr>\n\t\t<th>{}</th>", header)?;
+
+            for (index, v) in neighbor.dv.iter().enumerate() {
+                match self.advertised(neighbor, node.index, index) {
+                    (poisoned, Some(TermNote::PoisonReverse)) => writeln!(
+                        writer,
+                        "\t\t<td class=\"poisoned\" title=\"poisoned, really {}\">{}</td>",
+                        v.write_html_long(&names),
+                        poisoned.write_html()
+                    )?,
+                    _ => writeln!(writer, "\t\t<td>{}</td>", v.write_html_long(&names))?
+                }
+            }
+
+            writeln!(writer, "\t</tr>")?;
+        }
+
+        self.close_table(writer, node, removed)
+    }
+
+    /// Prints the links of `node` that were just removed and closes its table.
+    fn close_table<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        removed: &[(usize, usize)]
+    ) -> Result<(), Box<dyn Error>> {
+        let names = self.node_names();
+
+        for (_, removed_neighbor) in removed.iter().filter(|(a, _)| *a == node.index) {
+            writeln!(
+                writer,
+                "\t<tr class=\"removed\">\n\t\t<th>{}</th>\n\t\t<td colspan=\"{}\">link removed</td>\n\t</tr>",
+                names.get(removed_neighbor).unwrap(),
+                self.nodes.len()
+            )?;
+        }
+
+        writeln!(writer, "</table>")?;
+
+        Ok(())
+    }
+
+    fn build_world(
+        &self,
+        relations: &HashMap<(usize, usize), W>,
+        main_dvs: &HashMap<usize, Vec<DVValue<W>>>,
+        inbox_dvs: &HashMap<usize, Vec<DVValue<W>>>,
+        updated_nodes: &HashSet<usize>,
+        recompute_nodes: &HashSet<usize>,
+        advance_generation: bool
+    ) -> Self {
+        let mut nodes: Vec<Node<W>> = Vec::new();
+        let mut has_updates: HashSet<usize> = recompute_nodes.clone();
+        let mut random = self.random.clone();
+        let lost = self.lost_tables(relations, &mut random, advance_generation);
+        let mut messages = self.messages;
+
+        for node_index in updated_nodes {
+            // Nobody hears about changes on a silent node
+            if !self.nodes[*node_index].silent {
+                Self::update_has_updates(&mut has_updates, relations, &lost, *node_index);
+
+                if self.options.protocol != Protocol::LinkState {
+                    messages += relations.keys().filter(|(node_a, _)| node_a == node_index).count() as u32;
+                }
+            }
+        }
+
+        for node in &self.nodes {
+            let mut neighbors:Vec<Neighbor<W>> = Vec::new();
+
+            for ((node_a, node_b), new_w) in relations {
+                if *node_a == node.index {
+                    match node.neighbor(*node_b) {
+                        // A silent neighbor's last advertisement just gets older, so does a lost one
+                        Some(previous) if self.nodes[*node_b].silent || lost.contains(&(*node_b, node.index)) =>
+                            neighbors.push(Neighbor{
+                                index: *node_b,
+                                direct_cost: new_w.to_owned(),
+                                dv: previous.dv.clone(),
+                                age: if advance_generation { previous.age + 1 } else { previous.age }
+                            }),
+                        Some(previous) => {
+                            let dv = inbox_dvs.get(node_b).unwrap();
+
+                            // The first table after some were lost may bring news
+                            if previous.age > 0 || !same_routes(&previous.dv, dv) {
+                                has_updates.insert(node.index);
+                            }
+
+                            neighbors.push(Neighbor{
+                                index: *node_b,
+                                direct_cost: new_w.to_owned(),
+                                dv: dv.to_owned(),
+                                age: 0
+                            })
+                        }
+                        None => neighbors.push(Neighbor{
+                            index: *node_b,
+                            direct_cost: new_w.to_owned(),
+                            dv: inbox_dvs.get(node_b).unwrap().to_owned(),
+                            age: 0
+                        })
+                    }
+                }
+            }
+
+            neighbors.sort_by_key(|n| n.index);
+
+            nodes.push(Node {
+                name: node.name.to_owned(),
+                dv: main_dvs.get(&node.index).unwrap().to_owned(),
+                index: node.index,
+                has_updates: has_updates.contains(&node.index),
+                neighbors,
+                down_links: node.down_links.clone(),
+                silent: node.silent,
+                timers: node.timers.clone(),
+                hold_down: node.hold_down.clone(),
+                lsdb: node.lsdb.clone(),
+                dual: node.dual.clone()
+            });
+        }
+
+        let generation =
+            if advance_generation {
+                self.generation + 1
+            } else {
+                self.generation
+            };
+
+        World {
+            nodes,
+            generation,
+            options: self.options.clone(),
+            losses: self.losses.clone(),
+            random,
+            counting: self.counting.clone(),
+            loop_counts: self.loop_counts.clone(),
+            packets: self.packets.clone(),
+            route_checks: self.route_checks.clone(),
+            messages,
+            runs: self.runs.clone()
+        }
+    }
+
+    /// Every node sends its table to every neighbor each generation, this picks the
+    /// (sender, receiver) pairs whose table gets lost on the way.
+    fn lost_tables(
+        &self,
+        relations: &HashMap<(usize, usize), W>,
+        random: &mut Random,
+        advance_generation: bool
+    ) -> HashSet<(usize, usize)> {
+        let mut lost = HashSet::new();
+
+        if !advance_generation || self.losses.is_empty() {
+            return lost;
+        }
+
+        // Sorted so the same seed always loses the same tables
+        let mut links: Vec<(usize, usize)> = relations.keys().map(|(a, b)| (*b, *a)).collect();
+        links.sort();
+
+        for link in links {
+            if let Some(loss) = self.losses.get(&link) {
+                if random.chance(loss.loss) {
+                    lost.insert(link);
+                }
+            }
+        }
+
+        lost
+    }
+
+    fn copy_relations(&self) -> HashMap<(usize, usize), W> {
+        let mut relations: HashMap<(usize, usize), W> = HashMap::new();
+
+        for node in &self.nodes {
+            for neighbors in &node.neighbors {
+                relations.insert((node.index, neighbors.index), neighbors.direct_cost.to_owned());
+            }
+        }
+
+        relations
+    }
+
+    fn copy_dvs(&self) -> HashMap<usize, Vec<DVValue<W>>> {
+        let mut dvs: HashMap<usize, Vec<DVValue<W>>> = HashMap::new();
+
+        for node in &self.nodes {
+            let mut dv = Vec::new();
+
+            for v in &node.dv {
+                dv.push(v.clone());
+            }
+
+            dvs.insert(node.index, dv);
+        }
+
+        dvs
+    }
+
+    fn update_has_updates(
+        has_updates: &mut HashSet<usize>,
+        relations: &HashMap<(usize, usize), W>,
+        lost: &HashSet<(usize, usize)>,
+        node: usize
+    ){
+        for (node_a, node_b) in relations.keys() {
+            if *node_a == node && !lost.contains(&(node, *node_b)) {
+                has_updates.insert(*node_b);
+            }
+        }
+    }
+
+    pub fn apply_operations(&self, html_factory:&mut HtmlFiles, operations: Vec<Operation<W>>) -> Result<Self, Box<dyn Error>> {
+        let mut relations: HashMap<(usize, usize), W> = self.copy_relations();
+        let mut new_dvs: HashMap<usize, Vec<DVValue<W>>> = self.copy_dvs();
+        let mut updated_nodes: HashSet<usize> = HashSet::new();
+        // Nodes that lost a link have to recompute even if no neighbor sends them anything
+        let mut recompute_nodes: HashSet<usize> = HashSet::new();
+        let mut removed: Vec<(usize, usize)> = Vec::new();
+        let mut down_links: HashMap<usize, Option<Links<W>>> = HashMap::new();
+        let mut silent: HashMap<usize, bool> = HashMap::new();
+        let mut blank: Vec<usize> = Vec::new();
+
+        for op in operations {
+            match op {
+                Operation::ChangeWeight(node_a, node_b, new_w) => {
+                    relations.insert((node_a, node_b), new_w.to_owned());
+                    relations.insert((node_b, node_a), new_w.to_owned());
+
+                    new_dvs.insert(
+                        node_a,
+                        modify_dv(
+                            new_dvs.get(&node_a).unwrap(),
+                            node_b,
+                            new_w.to_owned(),
+                            self.options.max_metric.as_ref(),
+                        ),
+                    );
+
+                    new_dvs.insert(
+                        node_b,
+                        modify_dv(
+                            new_dvs.get(&node_b).unwrap(),
+                            node_a,
+                            new_w.to_owned(),
+                            self.options.max_metric.as_ref(),
+                        ),
+                    );
+
+                    updated_nodes.insert(node_a);
+                    updated_nodes.insert(node_b);
+                }
+                Operation::ChangeDirectedWeight(node_a, node_b, new_w) => {
+                    // Distance vectors travel against the link, so both directions have to exist
+                    if !relations.contains_key(&(node_b, node_a)) {
+                        return Err(format!(
+                            "can't set the weight from {} to {} without a link back",
+                            self.nodes[node_a].name,
+                            self.nodes[node_b].name
+                        ).into());
+                    }
+
+                    relations.insert((node_a, node_b), new_w.to_owned());
+
+                    new_dvs.insert(
+                        node_a,
+                        modify_dv(
+                            new_dvs.get(&node_a).unwrap(),
+                            node_b,
+                            new_w.to_owned(),
+                            self.options.max_metric.as_ref(),
+                        ),
+                    );
+
+                    updated_nodes.insert(node_a);
+                    recompute_nodes.insert(node_a);
+                }
+                Operation::RemoveLink(node_a, node_b) => {
+                    relations.remove(&(node_a, node_b));
+                    relations.remove(&(node_b, node_a));
+
+                    new_dvs.insert(
+                        node_a,
+                        invalidate_via(new_dvs.get(&node_a).unwrap(), node_b),
+                    );
+
+                    new_dvs.insert(
+                        node_b,
+                        invalidate_via(new_dvs.get(&node_b).unwrap(), node_a),
+                    );
+
+                    updated_nodes.insert(node_a);
+                    updated_nodes.insert(node_b);
+                    recompute_nodes.insert(node_a);
+                    recompute_nodes.insert(node_b);
+                    removed.push((node_a, node_b));
+                    removed.push((node_b, node_a));
+                }
+                Operation::NodeDown(node) => {
+                    let mut saved: Links<W> = relations
+                        .iter()
+                        .filter(|((node_a, node_b), _)| *node_a == node || *node_b == node)
+                        .map(|(relation, w)| (*relation, w.to_owned()))
+                        .collect();
+                    saved.sort_by_key(|(relation, _)| *relation);
+
+                    // The failed node keeps its table frozen, only its neighbors react
+                    for ((node_a, node_b), _) in &saved {
+                        relations.remove(&(*node_a, *node_b));
+
+                        if *node_a == node {
+                            new_dvs.insert(
+                                *node_b,
+                                invalidate_via(new_dvs.get(node_b).unwrap(), node),
+                            );
+
+                            updated_nodes.insert(*node_b);
+                            recompute_nodes.insert(*node_b);
+                            removed.push((*node_b, node));
+                        }
+                    }
+
+                    down_links.insert(node, Some(saved));
+                }
+                Operation::NodeUp(node, mode) => {
+                    let links_of = |down_links: &HashMap<usize, Option<Links<W>>>, n: usize| down_links
+                        .get(&n)
+                        .cloned()
+                        .unwrap_or_else(|| self.nodes[n].down_links.clone());
+                    let saved = links_of(&down_links, node).unwrap_or_default();
+
+                    if mode == RecoveryMode::Blank {
+                        new_dvs.insert(node, vec!(DVValue::Infinity; self.nodes.len()));
+                        blank.push(node);
+                    }
+
+                    for ((node_a, node_b), w) in saved {
+                        // A link to a node that is still down waits until that one comes up too
+                        let other = if node_a == node { node_b } else { node_a };
+                        if let Some(mut pending) = links_of(&down_links, other) {
+                            if !pending.iter().any(|(relation, _)| *relation == (node_a, node_b)) {
+                                pending.push(((node_a, node_b), w));
+                                pending.sort_by_key(|(relation, _)| *relation);
+                            }
+                            down_links.insert(other, Some(pending));
+                            continue;
+                        }
+
+                        relations.insert((node_a, node_b), w.to_owned());
+
+                        new_dvs.insert(
+                            node_a,
+                            modify_dv(
+                                new_dvs.get(&node_a).unwrap(),
+                                node_b,
+                                w,
+                                self.options.max_metric.as_ref(),
+                            ),
+                        );
+
+                        updated_nodes.insert(node_a);
+                    }
+
+                    recompute_nodes.insert(node);
+                    down_links.insert(node, None);
+                }
+                Operation::StopUpdates(node) => {
+                    silent.insert(node, true);
+                }
+                Operation::ResumeUpdates(node) => {
+                    // The first advertisement after the silence goes to every neighbor
+                    for (node_a, node_b) in relations.keys() {
+                        if *node_b == node {
+                            recompute_nodes.insert(*node_a);
+                        }
+                    }
+
+                    silent.insert(node, false);
+                }
+            }
+        }
+
+        // Every node whose links changed announces itself with a new sequence number
+        if self.options.protocol == Protocol::Dsdv {
+            for node in &updated_nodes {
+                let dv = new_dvs.get_mut(node).unwrap();
+                dv[*node] = DVValue::Dsdv(Cost::Zero, *node, next_sequence(dv[*node].sequence()));
+            }
+        }
+
+        let mut print_world = self.build_world(
+            &relations,
+            &new_dvs,
+            &self.copy_dvs(),
+            &updated_nodes,
+            &recompute_nodes,
+            false
+        );
+        print_world.set_down_links(&down_links);
+        print_world.set_silent(&silent);
+
+        html_factory.create(|writer|{
+            print_world.print_state(writer, &removed)
+        })?;
+
+        let mut world = self.build_world(
+            &relations,
+            &new_dvs,
+            &new_dvs,
+            &updated_nodes,
+            &recompute_nodes,
+            false
+        );
+        world.set_down_links(&down_links);
+        world.set_silent(&silent);
+        world.start_hold_downs(self);
+
+        // A blank restart forgets the topology and the DUAL state as well
+        for node in blank {
+            world.nodes[node].lsdb = Lsdb::default();
+            world.nodes[node].dual = Dual::default();
+        }
+
+        Ok(world)
+    }
+
+    fn set_down_links(&mut self, down_links: &HashMap<usize, Option<Links<W>>>) {
+        for (node, links) in down_links {
+            let node = &mut self.nodes[*node];
+
+            node.down_links = links.clone();
+            if node.is_down() {
+                node.has_updates = false;
+            }
+        }
+    }
+
+    fn set_silent(&mut self, silent: &HashMap<usize, bool>) {
+        for (node, silent) in silent {
+            self.nodes[*node].silent = *silent;
+
+            // A node that speaks again is heard right away
+            if !*silent {
+                let dv = self.nodes[*node].dv.clone();
+
+                for other in self.nodes.iter_mut() {
+                    for neighbor in other.neighbors.iter_mut().filter(|n| n.index == *node) {
+                        neighbor.dv = dv.clone();
+                        neighbor.age = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    fn timer_html(&self, timer: &RouteTimer, names: &BTreeMap<usize, String>) -> String {
+        match timer {
+            RouteTimer::Idle | RouteTimer::Age(0) => String::new(),
+            RouteTimer::Age(age) => format!(
+                "<sub class=\"timer\" title=\"rounds since the next hop refreshed the route\">{}</sub>",
+                age
+            ),
+            RouteTimer::GarbageCollection(via, rounds) => format!(
+                "<sub class=\"timer\" title=\"timed out through {}, removed in {} rounds\">gc {}</sub>",
+                names.get(via).unwrap(),
+                rounds,
+                rounds
+            )
+        }
+    }
+
+    /// Holds down every route that got worse since `before`.
+    fn start_hold_downs(&mut self, before: &World<W>) {
+        let rounds = match self.options.hold_down {
+            Some(rounds) if rounds > 0 => rounds,
+            _ => return
+        };
+
+        for (node, old_node) in self.nodes.iter_mut().zip(&before.nodes) {
+            for (target, (v, v_old)) in node.dv.iter().zip(&old_node.dv).enumerate() {
+                let cost: Cost<W> = v.into();
+                let old_cost: Cost<W> = v_old.into();
+
+                if let (Some(via), None, true) = (v_old.next_hop(target), node.hold_down[target], cost > old_cost) {
+                    node.hold_down[target] = Some(HoldDown { via, rounds });
+                }
+            }
+        }
+    }
+
+    fn next_hold_down(&self, node: &Node<W>, target: usize, v_old: &DVValue<W>, v: &DVValue<W>) -> Option<HoldDown> {
+        match (node.hold_down[target], self.options.hold_down) {
+            (Some(hold_down), _) if hold_down.rounds > 0 =>
+                Some(HoldDown { via: hold_down.via, rounds: hold_down.rounds - 1 }),
+            // The round after a hold-down ends listens to everyone again
+            (Some(_), _) => None,
+            (None, Some(rounds)) if rounds > 0 => {
+                let cost: Cost<W> = v.into();
+                let old_cost: Cost<W> = v_old.into();
+
+                match v_old.next_hop(target) {
+                    Some(via) if cost > old_cost => Some(HoldDown { via, rounds }),
+                    _ => None
+                }
+            }
+            _ => None
+        }
+    }
+
+    /// Whether the term through `through` is ignored because `target` is held down.
+    fn held_down(&self, node: &Node<W>, target: usize, through: usize) -> bool {
+        node.hold_down[target].is_some_and(|h| h.rounds > 0 && h.via != through)
+    }
+
+    /// Whether `node` has routes that are still going to time out, be removed or
+    /// leave hold-down, so it has to keep running even without updates.
+    fn timers_pending(&self, node: &Node<W>) -> bool {
+        if node.hold_down.iter().any(|h| h.is_some()) {
+            return true;
+        }
+
+        if self.options.timers.is_none() {
+            return false;
+        }
+
+        node.timers.iter().enumerate().any(|(target, timer)| match timer {
+            RouteTimer::GarbageCollection(..) => true,
+            RouteTimer::Age(_) => node.dv[target]
+                .next_hop(target)
+                .is_some_and(|via| self.nodes[via].silent || node.neighbor(via).is_some_and(|n| n.age > 0)),
+            RouteTimer::Idle => false
+        })
+    }
+
+    fn timed_out(&self, neighbour: &Neighbor<W>) -> bool {
+        match &self.options.timers {
+            Some(timers) => neighbour.age >= timers.timeout,
+            None => false
+        }
+    }
+
+    fn next_timer(
+        &self,
+        timers: &Timers,
+        node: &Node<W>,
+        target: usize,
+        v_old: &DVValue<W>,
+        v: &DVValue<W>
+    ) -> RouteTimer {
+        if let Some(via) = v.next_hop(target) {
+            return RouteTimer::Age(node.neighbor(via).map_or(0, |n| n.age));
+        }
+
+        match &node.timers[target] {
+            RouteTimer::GarbageCollection(via, rounds) if *rounds > 1 =>
+                RouteTimer::GarbageCollection(*via, rounds - 1),
+            RouteTimer::Age(_) => match v_old.next_hop(target).and_then(|via| node.neighbor(via)) {
+                Some(via) if self.timed_out(via) && timers.garbage_collection > 0 =>
+                    RouteTimer::GarbageCollection(via.index, timers.garbage_collection),
+                _ => RouteTimer::Idle
+            },
+            _ => RouteTimer::Idle
+        }
+    }
+
+    /// Whether the path `neighbour` advertises for `target` already goes through `receiver`,
+    /// which path vector rejects.
+    fn loops_back(&self, neighbour: &Neighbor<W>, receiver: usize, target: usize) -> bool {
+        self.options.protocol == Protocol::PathVector &&
+            neighbour.dv[target].path(target).is_some_and(|path| path.contains(&receiver))
+    }
+
+    /// Turns the route `node` picked for `target` into a path vector route, the next hop
+    /// followed by the path it advertised.
+    fn with_path(&self, node: &Node<W>, target: usize, v: DVValue<W>) -> DVValue<W> {
+        let via = match (self.options.protocol, v.next_hop(target)) {
+            (Protocol::PathVector, Some(via)) => via,
+            _ => return v
+        };
+
+        let mut path = vec!(via);
+        if via != target {
+            path.extend(node.neighbor(via).and_then(|n| n.dv[target].path(target)).unwrap_or_default());
+        }
+
+        match v.into() {
+            Cost::Value(w) => DVValue::Path(w, path),
+            cost => cost.to_dv_value(via, via == target)
+        }
+    }
+
+    /// The minimum of `formula`, picking between tied neighbors with the tie-breaking policy.
+    /// When there was a tie, also says how it was broken.
+    fn break_tie(
+        &self,
+        node: &Node<W>,
+        target: usize,
+        formula: &HtmlFormula<W>,
+        names: &BTreeMap<usize, String>
+    ) -> (DVValue<W>, Option<String>) {
+        let tied = formula.tied();
+        let lowest = match tied.iter().min() {
+            Some(lowest) if tied.len() > 1 => *lowest,
+            _ => return (formula.min_vector(), None)
+        };
+
+        let through = match self.options.tie_break {
+            TieBreak::LowestIndex => lowest,
+            TieBreak::LowestName => *tied.iter().min_by_key(|n| names.get(n)).unwrap(),
+            TieBreak::KeepCurrent => node.dv[target].next_hop(target).filter(|via| tied.contains(via)).unwrap_or(lowest),
+            TieBreak::Random => {
+                let seed = self.options.seed.unwrap_or(0) ^ ((node.index as u64) << 32) ^ target as u64;
+                tied[Random::new(seed).below(tied.len())]
+            }
+        };
+
+        let note = format!(
+            ", tie between {}, {} picks {}",
+            tied.iter().map(|n| names.get(n).unwrap().as_str()).collect::<Vec<_>>().join(", "),
+            self.options.tie_break,
+            names.get(&through).unwrap()
+        );
+
+        (formula.min_vector_through(through), Some(note))
+    }
+
+    /// Says on the page how ties are broken, for the protocols that take the minimum over
+    /// their neighbors.
+    fn tie_break_html(&self) -> String {
+        if self.options.ecmp || self.options.protocol == Protocol::LinkState {
+            return String::new();
+        }
+
+        format!("<div class=\"order\">ties: {}</div>\n", self.options.tie_break)
+    }
+
+    /// The newest sequence number `node` knows for `target`, from its own route and from
+    /// what its neighbors advertise. A route whose next hop timed out is broken, like after
+    /// a link broke it moves on to the odd number after its own. Always 0 unless the protocol
+    /// is DSDV.
+    fn newest_sequence(&self, node: &Node<W>, target: usize) -> u32 {
+        if self.options.protocol != Protocol::Dsdv {
+            return 0;
+        }
+
+        let route = &node.dv[target];
+        let broken = route
+            .next_hop(target)
+            .and_then(|via| node.neighbor(via))
+            .is_some_and(|via| self.timed_out(via));
+        let own = if broken { route.sequence() | 1 } else { route.sequence() };
+
+        node.neighbors
+            .iter()
+            .filter(|n| !self.timed_out(n))
+            .map(|n| n.dv[target].sequence())
+            .fold(own, u32::max)
+    }
+
+    /// Turns the route `node` picked for `target` into a DSDV route with `sequence`. An
+    /// unreachable target keeps the next hop it had.
+    fn with_sequence(&self, node: &Node<W>, target: usize, v: DVValue<W>, sequence: u32) -> DVValue<W> {
+        if self.options.protocol != Protocol::Dsdv {
+            return v;
+        }
+
+        let via = match (v.next_hop(target), &node.dv[target]) {
+            (Some(via), _) => via,
+            (None, DVValue::Dsdv(_, via, _)) => *via,
+            _ => target
+        };
+
+        DVValue::Dsdv(v.into(), via, sequence)
+    }
+
+    /// The distance `neighbour` advertises to `receiver` for `target`, after the horizon rules.
+    fn advertised(&self, neighbour: &Neighbor<W>, receiver: usize, target: usize) -> (Cost<W>, Option<TermNote>) {
+        let value = neighbour.dv.get(target).unwrap();
+
+        if self.timed_out(neighbour) {
+            return (Cost::Infinity, Some(TermNote::TimedOut));
+        }
+
+        match (self.options.horizon, value) {
+            (HorizonMode::SplitHorizon, DVValue::Distance(_, via)) if *via == receiver =>
+                (Cost::Infinity, Some(TermNote::SplitHorizon)),
+            (HorizonMode::PoisonReverse, DVValue::Distance(_, via)) if *via == receiver =>
+                (Cost::Infinity, Some(TermNote::PoisonReverse)),
+            (HorizonMode::SplitHorizon, DVValue::Path(_, path)) if path.first() == Some(&receiver) =>
+                (Cost::Infinity, Some(TermNote::SplitHorizon)),
+            (HorizonMode::PoisonReverse, DVValue::Path(_, path)) if path.first() == Some(&receiver) =>
+                (Cost::Infinity, Some(TermNote::PoisonReverse)),
+            (HorizonMode::SplitHorizon, DVValue::Dsdv(Cost::Value(_), via, _)) if *via == receiver =>
+                (Cost::Infinity, Some(TermNote::SplitHorizon)),
+            (HorizonMode::PoisonReverse, DVValue::Dsdv(Cost::Value(_), via, _)) if *via == receiver =>
+                (Cost::Infinity, Some(TermNote::PoisonReverse)),
+            (HorizonMode::SplitHorizon, DVValue::Multipath(_, hops)) if hops.contains(&receiver) =>
+                (Cost::Infinity, Some(TermNote::SplitHorizon)),
+            (HorizonMode::PoisonReverse, DVValue::Multipath(_, hops)) if hops.contains(&receiver) =>
+                (Cost::Infinity, Some(TermNote::PoisonReverse)),
+            _ => (value.into(), None)
+        }
+    }
+
+    fn print_state<Writer: Write>(&self, writer: &mut Writer, removed: &[(usize, usize)]) -> Result<(), Box<dyn Error>> {
+        writeln!(writer, "<h2>t={}</h2>", self.generation)?;
+
+        let names = self.node_names();
+        let loops = self.forwarding_loops(&self.copy_dvs());
+        let warnings: Vec<String> = loops.iter().map(|l| l.describe(&names)).collect();
+        write!(writer, "{}", warning_banner(&warnings))?;
+
+        for node in &self.nodes {
+            match self.options.protocol {
+                Protocol::DistanceVector | Protocol::PathVector | Protocol::Dsdv =>
+                    self.print_node(writer, node, None, &node.timers, removed, &loops)?,
+                Protocol::LinkState => self.print_link_state_node(writer, node, None, &HashSet::new(), removed, &loops)?,
+                Protocol::Dual => self.print_dual_node(writer, node, None, removed, &loops)?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recomputes the table of `node` from what its neighbors last advertised.
+    fn compute_node(&self, node: &Node<W>, names: &BTreeMap<usize, String>) -> Computed<W> {
+        let mut new_dv = Vec::new();
+        let mut node_timers = node.timers.clone();
+        let mut node_hold_downs = node.hold_down.clone();
+
+        let mut lines: Vec<String> = Vec::new();
+
+        for (index, v_old) in node.dv.iter().enumerate() {
+            if index == node.index && self.options.protocol == Protocol::Dsdv {
+                // Someone announced a broken route to this node, only a newer number fixes it
+                let newest = self.newest_sequence(node, index);
+                let sequence = if newest > v_old.sequence() { next_sequence(newest) } else { newest };
+
+                new_dv.push(DVValue::Dsdv(Cost::Zero, index, sequence));
+            } else if index == node.index {
+                new_dv.push(DVValue::SameNode);
+            } else {
+                // For debug printing
+                let mut formula =
+                    HtmlFormula::new(index, node.index, self.options.max_metric.clone());
+                let newest = self.newest_sequence(node, index);
+
+                for neighbour in &node.neighbors {
+                    let held_down = self.held_down(node, index, neighbour.index);
+                    let sequence = neighbour.dv[index].sequence();
+                    let older = sequence < newest && !self.timed_out(neighbour);
+
+                    if neighbour.index == index && self.timed_out(neighbour) {
+                        formula.add_direct(
+                            neighbour.index,
+                            node.index,
+                            Cost::Infinity,
+                            Some(if held_down { TermNote::HoldDown } else { TermNote::TimedOut })
+                        );
+                    } else if neighbour.index == index {
+                        formula.add_direct(
+                            neighbour.index,
+                            node.index,
+                            Cost::Value(neighbour
+                                .direct_cost
+                                .to_owned()),
+                            if held_down {
+                                Some(TermNote::HoldDown)
+                            } else if older {
+                                Some(TermNote::OlderSequence)
+                            } else {
+                                None
+                            }
+                        );
+                    } else {
+                        let (advertised, note) =
+                            self.advertised(neighbour, node.index, index);
+                        let note = if held_down {
+                            Some(TermNote::HoldDown)
+                        } else if advertised != Cost::Infinity && self.loops_back(neighbour, node.index, index) {
+                            Some(TermNote::PathLoop)
+                        } else if older {
+                            Some(TermNote::OlderSequence)
+                        } else {
+                            note
+                        };
+
+                        formula.add_indirect(
+                            neighbour.index,
+                            node.index,
+                            neighbour
+                                .direct_cost
+                                .to_owned(),
+                            index,
+                            neighbour.index,
+                            advertised,
+                            note
+                        );
+                    }
+
+                    if self.options.protocol == Protocol::Dsdv {
+                        formula.set_sequence(sequence);
+                    }
+                }
+
+                let (v, tie) = if self.options.ecmp {
+                    (formula.min_multipath(), None)
+                } else {
+                    self.break_tie(node, index, &formula, names)
+                };
+                let v = self.with_path(node, index, v);
+                let v = self.with_sequence(node, index, v, newest);
+
+                if let Some(timers) = &self.options.timers {
+                    node_timers[index] = self.next_timer(timers, node, index, v_old, &v);
+                }
+
+                let mut line = formula.render(names) + tie.unwrap_or_default().as_str();
+                if let Some(hold_down) = node.hold_down[index].filter(|h| h.rounds > 0) {
+                    line += format!(
+                        " <span class=\"note\">hold-down through {}, {} round{} left</span>",
+                        names.get(&hold_down.via).unwrap(),
+                        hold_down.rounds,
+                        if hold_down.rounds == 1 { "" } else { "s" }
+                    ).as_str();
+                }
+
+                node_hold_downs[index] = self.next_hold_down(node, index, v_old, &v);
+                new_dv.push(v);
+                lines.push(line);
+            }
+        }
+
+        Computed { dv: new_dv, timers: node_timers, hold_down: node_hold_downs, lines }
+    }
+
+    /// Prints a recomputed node, with the formulas that produced its new table.
+    fn print_computed<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        computed: &Computed<W>,
+        loops: &[ForwardingLoop]
+    ) -> Result<(), Box<dyn Error>> {
+        self.print_node(writer, node, Some(&computed.dv), &computed.timers, &[], loops)?;
+        writeln!(writer, "<div class=\"details\">")?;
+        for line in &computed.lines {
+            writeln!(writer, "\t<div>{}</div>", line)?;
+        }
+        writeln!(writer, "</div>")?;
+
+        Ok(())
+    }
+
+    /// The order nodes compute in this generation, drawing a new one for random order.
+    fn update_order(&mut self) -> Result<Vec<usize>, Box<dyn Error>> {
+        let mut order: Vec<usize> = Vec::with_capacity(self.nodes.len());
+
+        match self.options.update_order {
+            UpdateOrder::Simultaneous => {}
+            UpdateOrder::Sequential => for name in &self.options.order {
+                let index = self.node_index(name)?;
+
+                if order.contains(&index) {
+                    return Err(format!("{} is in the update order twice", name).into());
+                }
+
+                order.push(index);
+            },
+            UpdateOrder::Random => {
+                order = (0..self.nodes.len()).collect();
+                self.random.shuffle(&mut order);
+            }
+        }
+
+        for node in &self.nodes {
+            if !order.contains(&node.index) {
+                order.push(node.index);
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Hands a table that was just computed to the node's neighbors within the same
+    /// generation, for sequential update orders. Links in `lost` lose it like they
+    /// lose the table at the end of the generation.
+    fn share(&mut self, node: usize, computed: &Computed<W>, lost: &HashSet<(usize, usize)>) {
+        let changed = !same_routes(&self.nodes[node].dv, &computed.dv);
+
+        self.nodes[node].dv = computed.dv.clone();
+        self.nodes[node].timers = computed.timers.clone();
+        self.nodes[node].hold_down = computed.hold_down.clone();
+
+        if !changed || self.nodes[node].silent {
+            return;
+        }
+
+        for other in self.nodes.iter_mut().filter(|o| !lost.contains(&(node, o.index))) {
+            for neighbor in other.neighbors.iter_mut().filter(|n| n.index == node) {
+                neighbor.dv = computed.dv.clone();
+                other.has_updates = true;
+            }
+        }
+    }
+
+    pub fn run_simulation(&self, html_factory: &mut HtmlFiles) -> Result<NewState<W>, Box<dyn Error>> {
+        let mut writer:Vec<u8> = Vec::new();
+
+        let mut updated_nodes: HashSet<usize> = HashSet::new();
+        let mut new_dvs: HashMap<usize, Vec<DVValue<W>>> = self.copy_dvs();
+        let mut new_timers: HashMap<usize, Vec<RouteTimer>> = HashMap::new();
+        let mut new_hold_downs: HashMap<usize, Vec<Option<HoldDown>>> = HashMap::new();
+        let names = self.node_names();
+
+        // Sequential orders change this as they go, so later nodes see what earlier ones computed
+        let mut current = self.clone();
+        let sequential = self.options.update_order != UpdateOrder::Simultaneous;
+        let order = current.update_order()?;
+        // The same draws build_world makes at the end, so a table that's lost there
+        // isn't handed over during the generation either
+        let lost = if sequential {
+            current.lost_tables(&self.copy_relations(), &mut current.random.clone(), true)
+        } else {
+            HashSet::new()
+        };
+
+        if sequential {
+            writeln!(
+                writer,
+                "<div class=\"order\">update order: {}</div>",
+                order.iter().map(|i| names.get(i).unwrap().as_str()).collect::<Vec<_>>().join(", ")
+            )?;
+        }
+        write!(writer, "{}", self.tie_break_html())?;
+
+        // Printed once every table is known, so the forwarding loops can be marked
+        let mut printed: Vec<(Node<W>, Option<Computed<W>>)> = Vec::new();
+
+        for index in order {
+            let node = current.nodes[index].clone();
+
+            if (node.has_updates || current.timers_pending(&node)) && !node.is_down() {
+                let computed = current.compute_node(&node, &names);
+
+                if !same_routes(&computed.dv, &node.dv) {
+                    updated_nodes.insert(node.index);
+                }
+
+                if sequential {
+                    current.share(node.index, &computed, &lost);
+                }
+
+                new_dvs.insert(node.index, computed.dv.clone());
+                new_timers.insert(node.index, computed.timers.clone());
+                new_hold_downs.insert(node.index, computed.hold_down.clone());
+                printed.push((node, Some(computed)));
+            } else {
+                new_dvs.insert(node.index, node.dv.clone());
+                printed.push((node, None));
+            }
+        }
+
+        let loops = self.forwarding_loops(&new_dvs);
+
+        for (node, computed) in &printed {
+            match computed {
+                Some(computed) => current.print_computed(&mut writer, node, computed, &loops)?,
+                None => current.print_node(&mut writer, node, None, &node.timers, &[], &loops)?
+            }
+        }
+
+        let (counting, mut warnings) = self.track_counting(&new_dvs, self.generation + 1);
+        warnings.extend(loops.iter().map(|l| l.describe(&names)));
+
+        html_factory.create(|w| {
+            writeln!(w, "<h2>t={}</h2>", self.generation + 1)?;
+            w.write_all(warning_banner(&warnings).as_bytes())?;
+            w.write_all(writer.as_slice())?;
+            Ok(())
+        })?;
+
+        let timers_changed = new_timers
+            .iter()
+            .any(|(node, timers)| *timers != self.nodes[*node].timers) ||
+            new_hold_downs
+                .iter()
+                .any(|(node, hold_downs)| *hold_downs != self.nodes[*node].hold_down);
+
+        let mut world = current.build_world(
+            &self.copy_relations(),
+            &new_dvs,
+            &new_dvs,
+            &updated_nodes,
+            &HashSet::new(),
+            true
+        );
+
+        for (node, timers) in new_timers {
+            world.nodes[node].timers = timers;
+        }
+
+        for (node, hold_downs) in new_hold_downs {
+            world.nodes[node].hold_down = hold_downs;
+        }
+
+        world.counting = counting;
+        if !loops.is_empty() {
+            world.loop_counts.push((world.generation, loops.len()));
+        }
+
+        let generation = world.generation;
+        world.move_packets(generation, &Delays::new());
+
+        if updated_nodes.is_empty() && !timers_changed && !world.nodes.iter().any(|n| world.timers_pending(n)) {
+            Ok(NewState::NotChanged)
+        } else {
+            Ok(NewState::Changed(Box::new(world)))
+        }
+    }
+}
+
+
+
+impl<W: Metric> World<W> {
+    /// Fingerprint of everything the next generations depend on: the tables, what every
+    /// node last heard from its neighbors and the timers. The random generator is left out,
+    /// so that lossy runs find their repeated states too.
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        for node in &self.nodes {
+            for v in &node.dv {
+                v.hash_route(&mut hasher);
+            }
+
+            for neighbor in &node.neighbors {
+                neighbor.index.hash(&mut hasher);
+                neighbor.direct_cost.hash(&mut hasher);
+                neighbor.age.hash(&mut hasher);
+
+                for v in &neighbor.dv {
+                    v.hash_route(&mut hasher);
+                }
+            }
+
+            node.timers.hash(&mut hasher);
+            node.hold_down.hash(&mut hasher);
+            node.has_updates.hash(&mut hasher);
+            node.silent.hash(&mut hasher);
+            node.is_down().hash(&mut hasher);
+            node.lsdb.hash(&mut hasher);
+            node.dual.hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+}
+
+impl<W: Metric> Node<W> {
+    /// Whether two nodes are in the same state, by everything `state_hash` looks at.
+    fn same_state(&self, other: &Node<W>) -> bool {
+        same_routes(&self.dv, &other.dv) &&
+            self.neighbors.len() == other.neighbors.len() &&
+            self.neighbors.iter().zip(&other.neighbors).all(|(a, b)| {
+                a.index == b.index && a.direct_cost == b.direct_cost && a.age == b.age && same_routes(&a.dv, &b.dv)
+            }) &&
+            self.timers == other.timers &&
+            self.hold_down == other.hold_down &&
+            self.has_updates == other.has_updates &&
+            self.silent == other.silent &&
+            self.is_down() == other.is_down() &&
+            self.lsdb == other.lsdb &&
+            self.dual == other.dual
+    }
+}
+
+/// Earlier states by their hash, compared in full since hashes can collide: the generation,
+/// the nodes and the random generator
+type Visits<W> = HashMap<u64, Vec<(u32, Vec<Node<W>>, Random)>>;
+
+/// Whether every node is in the same state in both lists.
+fn same_nodes<W: Metric>(a: &[Node<W>], b: &[Node<W>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.same_state(y))
+}
+
+/// Runs generations until nothing changes, the generation limit is reached or
+/// the state repeats itself. The limit counts the generations of this run only.
+/// Converged tables are checked against the shortest paths.
+///
+/// A state that comes back with the same random generator repeats forever. When random
+/// numbers were drawn in between, losses or a random order may still get the run out of
+/// it, so it goes on and only reports the oscillation if it hits the limit.
+pub fn run_until_stable<W: Metric>(
+    html_factory: &mut HtmlFiles,
+    mut world: World<W>
+) -> Result<(World<W>, Convergence), Box<dyn Error>> {
+    let mut seen: Visits<W> = HashMap::new();
+    let mut revisited = None;
+    let started = world.generation;
+
+    loop {
+        let earlier = seen.entry(world.state_hash()).or_default();
+        let repeated: Vec<_> = earlier
+            .iter()
+            .filter(|(_, nodes, _)| same_nodes(nodes, &world.nodes))
+            .collect();
+        if let Some((previous, _, _)) = repeated.iter().find(|(_, _, random)| *random == world.random) {
+            let period = world.generation - previous;
+            return Ok((world, Convergence::Oscillating(period)));
+        }
+        if let Some((previous, _, _)) = repeated.last() {
+            revisited = Some(world.generation - previous);
+        }
+        earlier.push((world.generation, world.nodes.clone(), world.random.clone()));
+
+        if world.generation - started >= world.options.max_generations {
+            let convergence = revisited.map_or(Convergence::GenerationLimit, Convergence::Oscillating);
+            return Ok((world, convergence));
+        }
+
+        let next = match world.options.protocol {
+            Protocol::DistanceVector | Protocol::PathVector | Protocol::Dsdv => world.run_simulation(html_factory)?,
+            Protocol::LinkState => world.run_link_state(html_factory)?,
+            Protocol::Dual => world.run_dual(html_factory)?
+        };
+
+        match next {
+            NewState::Changed(next) => world = *next,
+            // When no-change advance the generation on by 1
+            NewState::NotChanged => {
+                let mut stable = World {
+                    generation: world.generation + 1,
+                    ..world
+                };
+                stable.check_routes();
+
+                return Ok((stable, Convergence::Converged));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::scenario::Scenario;
+
+    const D: usize = 3;
+
+    /// Runs a scenario written in TOML, its pages go to a directory of their own.
+    pub(crate) fn run_scenario(name: &str, toml: &str) -> (World<u32>, Convergence) {
+        let scenario: Scenario = toml::from_str(toml).unwrap();
+        let folder = env::temp_dir().join("dvr_sim_tests").join(name);
+        let mut html_factory = HtmlFiles::new(folder.to_str().unwrap(), name);
+
+        scenario.run(&mut html_factory).unwrap()
+    }
+
+    /// The next hop A picks for D when Z and C tie, with `current` as its route so far.
+    fn tie_break(tie_break: TieBreak, seed: Option<u64>, current: DVValue<u32>) -> usize {
+        let mut world: World<u32> = World::new(vec!("A", "Z", "C", "D"));
+        world.options.tie_break = tie_break;
+        world.options.seed = seed;
+        world.nodes[0].dv[D] = current;
+
+        let mut formula = HtmlFormula::new(D, 0, None);
+        formula.add_indirect(1, 0, 1, D, 1, Cost::Value(1), None);
+        formula.add_indirect(2, 0, 1, D, 2, Cost::Value(1), None);
+
+        let (v, note) = world.break_tie(&world.nodes[0], D, &formula, &world.node_names());
+        assert!(note.is_some());
+        v.next_hop(D).unwrap()
+    }
+
+    #[test]
+    fn ties_are_broken_by_the_policy() {
+        assert_eq!(tie_break(TieBreak::LowestIndex, None, DVValue::Infinity), 1);
+        assert_eq!(tie_break(TieBreak::LowestName, None, DVValue::Infinity), 2);
+        assert_eq!(tie_break(TieBreak::KeepCurrent, None, DVValue::Distance(2, 2)), 2);
+        // Without a current route among the tied ones it falls back to the lowest index
+        assert_eq!(tie_break(TieBreak::KeepCurrent, None, DVValue::Distance(2, 0)), 1);
+    }
+
+    #[test]
+    fn random_ties_depend_only_on_the_seed() {
+        for seed in 0..8 {
+            let picked = tie_break(TieBreak::Random, Some(seed), DVValue::Infinity);
+            assert!(picked == 1 || picked == 2);
+            assert_eq!(tie_break(TieBreak::Random, Some(seed), DVValue::Distance(2, 2)), picked);
+        }
+
+        let picks: HashSet<usize> = (0..8).map(|seed| tie_break(TieBreak::Random, Some(seed), DVValue::Infinity)).collect();
+        assert_eq!(picks.len(), 2);
+    }
+
+    #[test]
+    fn repeated_states_leave_the_random_generator_out() {
+        let world: World<u32> = World::new(vec!("A", "B")).with_options(Options { seed: Some(1), ..Options::default() });
+        let mut later = world.clone();
+        later.random.next_u64();
+        later.generation = 4;
+
+        assert_eq!(world.state_hash(), later.state_hash());
+        assert!(same_nodes(&world.nodes, &later.nodes));
+
+        // A different next hop at the same cost is a different state
+        later.nodes[0].dv[1] = DVValue::Distance(1, 0);
+        let mut rerouted = later.clone();
+        rerouted.nodes[0].dv[1] = DVValue::Distance(1, 1);
+        assert!(!same_nodes(&later.nodes, &rerouted.nodes));
+    }
+
+    #[test]
+    fn links_to_a_node_that_is_still_down_wait_for_it() {
+        let (world, convergence) = run_scenario("node_up_next_to_down", r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "B", b = "C", weight = 1 },
+                { a = "C", b = "D", weight = 1 },
+                { a = "A", b = "D", weight = 5 },
+            ]
+
+            [options]
+            max_metric = 16
+
+            [[steps]]
+            operations = [{ op = "node_down", node = "B" }]
+
+            [[steps]]
+            operations = [{ op = "node_down", node = "C" }]
+
+            [[steps]]
+            operations = [{ op = "node_up", node = "B" }]
+
+            [[steps]]
+            operations = [{ op = "node_up", node = "C" }]
+        "#);
+
+        assert_eq!(convergence, Convergence::Converged);
+        // B came back next to a dead C without routing through it
+        assert_eq!(world.route_check_report(), vec!("routes match the shortest paths at t=3, t=18, t=21, t=23, t=26"));
+        // And got its link to C back once C came up too
+        assert!(world.nodes[1].neighbor(2).is_some());
+        assert!(world.nodes[2].neighbor(1).is_some());
+        assert!(world.nodes.iter().all(|n| !n.is_down()));
+    }
+
+    #[test]
+    fn dsdv_routes_through_a_timed_out_neighbor_take_an_odd_number() {
+        let (world, convergence) = run_scenario("dsdv_timeout", r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "A", b = "D", weight = 4 },
+                { a = "B", b = "C", weight = 7 },
+                { a = "B", b = "D", weight = 2 },
+                { a = "C", b = "D", weight = 3 },
+            ]
+
+            [options]
+            protocol = "dsdv"
+            max_metric = 16
+            timers = { timeout = 3, garbage_collection = 2 }
+
+            [[steps]]
+            operations = [{ op = "stop_updates", node = "B" }]
+        "#);
+
+        assert_eq!(convergence, Convergence::Converged);
+        // The stale routes through the others have an older number, nobody counts up on them
+        assert!(world.counting_report().is_empty());
+        assert!(world.loop_counts().is_empty());
+        for node in [0, 2, 3] {
+            assert!(matches!(world.nodes[node].dv[1], DVValue::Dsdv(Cost::Infinity, _, 3)));
+        }
+    }
+
+    #[test]
+    fn removing_a_link_drops_the_neighbors_and_the_routes_over_it() {
+        let (world, convergence) = run_scenario("remove_link", r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "A", b = "D", weight = 4 },
+                { a = "B", b = "C", weight = 7 },
+                { a = "B", b = "D", weight = 2 },
+                { a = "C", b = "D", weight = 3 },
+            ]
+
+            [[steps]]
+            operations = [{ op = "remove_link", a = "B", b = "D" }]
+        "#);
+
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(world.nodes[1].neighbor(3).is_none());
+        assert!(world.nodes[3].neighbor(1).is_none());
+        // B goes round through A now, and D the same way back
+        assert!(world.nodes[1].dv[3].same_route(&DVValue::Distance(5, 0), 3));
+        assert!(world.nodes[3].dv[1].same_route(&DVValue::Distance(5, 0), 1));
+    }
+
+    #[test]
+    fn a_node_that_goes_down_becomes_unreachable_and_comes_back() {
+        let down = r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "B", b = "C", weight = 2 },
+                { a = "C", b = "D", weight = 1 },
+                { a = "A", b = "D", weight = 5 },
+            ]
+
+            [options]
+            max_metric = 16
+
+            [[steps]]
+            operations = [{ op = "node_down", node = "C" }]
+        "#;
+        let (world, convergence) = run_scenario("node_down", down);
+
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(world.nodes[2].is_down());
+        for node in [0, 1, 3] {
+            assert!(matches!(world.nodes[node].dv[2], DVValue::Infinity));
+            assert!(world.nodes[node].neighbor(2).is_none());
+        }
+        // D now goes the long way round to B
+        assert!(world.nodes[3].dv[1].same_route(&DVValue::Distance(6, 0), 1));
+
+        for mode in ["keep", "blank"] {
+            let up = format!("{down}\n[[steps]]\noperations = [{{ op = \"node_up\", node = \"C\", mode = \"{mode}\" }}]\n");
+            let (world, convergence) = run_scenario(&format!("node_up_{mode}"), &up);
+
+            assert_eq!(convergence, Convergence::Converged);
+            assert!(!world.nodes[2].is_down());
+            assert!(world.nodes[3].dv[1].same_route(&DVValue::Distance(3, 2), 1));
+            assert!(world.nodes[2].dv[0].same_route(&DVValue::Distance(3, 1), 0));
+        }
+    }
+
+    #[test]
+    fn each_direction_of_a_link_has_its_own_cost() {
+        let links = r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "A", b = "D", weight = 4, reverse_weight = 2 },
+                { a = "B", b = "C", weight = 7 },
+                { a = "B", b = "D", weight = 2 },
+                { a = "C", b = "D", weight = 3, reverse_weight = 6 },
+            ]
+        "#;
+        let (world, convergence) = run_scenario("asymmetric", links);
+
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(world.nodes[0].dv[3].same_route(&DVValue::Distance(3, 1), 3));
+        assert!(world.nodes[3].dv[0].same_route(&DVValue::Distance(2, 0), 0));
+        assert!(world.nodes[2].dv[0].same_route(&DVValue::Distance(5, 3), 0));
+        assert!(world.nodes[0].dv[2].same_route(&DVValue::Distance(8, 1), 2));
+
+        let changed = format!("{links}\n[[steps]]\noperations = [{{ op = \"change_directed_weight\", a = \"B\", b = \"D\", weight = 60 }}]\n");
+        let (world, convergence) = run_scenario("asymmetric_change", &changed);
+
+        assert_eq!(convergence, Convergence::Converged);
+        // Only B to D got dearer, D to B is still 2
+        assert!(world.nodes[1].dv[3].same_route(&DVValue::Distance(5, 0), 3));
+        assert!(world.nodes[3].dv[1].same_route(&DVValue::Distance(2, 1), 1));
+        assert!(world.nodes[0].dv[3].same_route(&DVValue::Distance(4, 3), 3));
+    }
+
+    /// A line A - B - C that loses its B - C link, under the given horizon mode.
+    fn lose_the_end_of_a_line(horizon: &str) -> World<u32> {
+        let (world, convergence) = run_scenario(&format!("line_{horizon}"), &format!(r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+            ]
+
+            [options]
+            max_metric = 16
+            horizon = "{horizon}"
+
+            [[steps]]
+            operations = [{{ op = "remove_link", a = "B", b = "C" }}]
+        "#));
+
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(matches!(world.nodes[0].dv[2], DVValue::Infinity));
+        assert!(matches!(world.nodes[1].dv[2], DVValue::Infinity));
+        world
+    }
+
+    #[test]
+    fn split_horizon_stops_counting_to_infinity_on_a_line() {
+        assert_eq!(lose_the_end_of_a_line("plain").counting_report(),
+            vec!("count to infinity towards C through A, B from t=3 for 13 rounds, ended unreachable"));
+        assert!(lose_the_end_of_a_line("split_horizon").counting_report().is_empty());
+    }
+
+    #[test]
+    fn poison_reverse_advertises_infinity_back_to_the_next_hop() {
+        assert!(lose_the_end_of_a_line("poison_reverse").counting_report().is_empty());
+
+        // B reaches C through A, and tells A so in each mode
+        let neighbour: Neighbor<u32> = Neighbor {
+            index: 1,
+            direct_cost: 1,
+            dv: vec!(DVValue::Distance(1, 0), DVValue::Distance(0, 1), DVValue::Distance(2, 0)),
+            age: 0
+        };
+        let advertised = |horizon| {
+            let world: World<u32> = World::new(vec!("A", "B", "C")).with_options(Options { horizon, ..Options::default() });
+            world.advertised(&neighbour, 0, 2)
+        };
+
+        assert_eq!(advertised(HorizonMode::Plain), (Cost::Value(2), None));
+        assert_eq!(advertised(HorizonMode::SplitHorizon), (Cost::Infinity, Some(TermNote::SplitHorizon)));
+        assert_eq!(advertised(HorizonMode::PoisonReverse), (Cost::Infinity, Some(TermNote::PoisonReverse)));
+    }
+
+    #[test]
+    fn the_maximum_metric_ends_counting_to_infinity() {
+        let line = |options: &str| run_scenario("line_max_metric", &format!(r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+            ]
+
+            [options]
+            {options}
+
+            [[steps]]
+            operations = [{{ op = "remove_link", a = "B", b = "C" }}]
+        "#));
+
+        // Without one A and B count up until the run gives up
+        let (world, convergence) = line("max_generations = 30");
+        assert_eq!(convergence, Convergence::GenerationLimit);
+        assert!(matches!(world.nodes[0].dv[2], DVValue::Distance(..)));
+
+        // With RIP's 16 they both end at infinity
+        let (world, convergence) = line("max_metric = 16");
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(matches!(world.nodes[0].dv[2], DVValue::Infinity));
+        assert!(matches!(world.nodes[1].dv[2], DVValue::Infinity));
+    }
+
+    #[test]
+    fn routes_through_a_silent_neighbor_time_out() {
+        let silent = r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "A", b = "D", weight = 4 },
+                { a = "B", b = "C", weight = 7 },
+                { a = "B", b = "D", weight = 2 },
+                { a = "C", b = "D", weight = 3 },
+            ]
+
+            [options]
+            max_metric = 16
+            timers = { timeout = 3, garbage_collection = 2 }
+
+            [[steps]]
+            operations = [{ op = "stop_updates", node = "B" }]
+        "#;
+        let (world, convergence) = run_scenario("timers_silent", silent);
+
+        assert_eq!(convergence, Convergence::Converged);
+        for node in [0, 2, 3] {
+            assert!(matches!(world.nodes[node].dv[1], DVValue::Infinity));
+        }
+        // A and D stop going through B to reach each other
+        assert!(world.nodes[0].dv[3].same_route(&DVValue::Distance(4, 3), 3));
+        assert!(world.nodes[2].dv[0].same_route(&DVValue::Distance(7, 3), 0));
+        // And the unreachable routes got collected afterwards
+        assert!(world.nodes.iter().all(|n| n.timers.iter().all(|t| !matches!(t, RouteTimer::GarbageCollection(..)))));
+
+        let resumed = format!("{silent}\n[[steps]]\noperations = [{{ op = \"resume_updates\", node = \"B\" }}]\n");
+        let (world, convergence) = run_scenario("timers_resumed", &resumed);
+
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(world.nodes[0].dv[3].same_route(&DVValue::Distance(3, 1), 3));
+        assert!(world.nodes[2].dv[1].same_route(&DVValue::Distance(5, 3), 1));
+    }
+
+    #[test]
+    fn hold_down_keeps_nodes_from_taking_stale_routes() {
+        let tir1 = |options: &str| run_scenario("hold_down", &format!(r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                {{ a = "A", b = "B", weight = 2 }},
+                {{ a = "A", b = "D", weight = 10 }},
+                {{ a = "B", b = "C", weight = 1 }},
+                {{ a = "B", b = "D", weight = 20 }},
+                {{ a = "C", b = "D", weight = 2 }},
+            ]
+
+            [options]
+            {options}
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "C", b = "D", weight = 80 }}]
+        "#));
+
+        let (world, _) = tir1("");
+        assert_eq!(world.loop_counts().len(), 7);
+
+        // A held down route only listens to its old next hop, so the loop lasts a single round
+        let (world, convergence) = tir1("hold_down = 3");
+        assert_eq!(convergence, Convergence::Converged);
+        assert_eq!(world.loop_counts(), [(4, 1)]);
+        assert_eq!(world.route_check_report(), vec!("routes match the shortest paths at t=3, t=12"));
+        assert!(world.nodes.iter().all(|n| n.hold_down.iter().all(|h| h.is_none_or(|h| h.rounds == 0))));
+    }
+
+    #[test]
+    fn losses_follow_the_seed() {
+        let lossy = |seed: u64| run_scenario("lossy", &format!(r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "A", b = "D", weight = 4 }},
+                {{ a = "B", b = "C", weight = 7, loss = 0.3 }},
+                {{ a = "B", b = "D", weight = 2, loss = 0.3, duplication = 0.2 }},
+                {{ a = "C", b = "D", weight = 3 }},
+            ]
+
+            [options]
+            max_metric = 16
+            timers = {{ timeout = 3, garbage_collection = 2 }}
+            seed = {seed}
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "B", b = "D", weight = 9 }}]
+        "#));
+        let describe = |world: &World<u32>| world.runs().iter().map(|r| r.describe()).collect::<Vec<String>>();
+
+        let (first, _) = lossy(7);
+        let (again, _) = lossy(7);
+        assert_eq!(first.generation, again.generation);
+        assert_eq!(describe(&first), describe(&again));
+        assert!(same_nodes(&first.nodes, &again.nodes));
+        assert_eq!(first.route_check_report(), vec!("routes match the shortest paths at t=5, t=18"));
+
+        // Other seeds lose other tables and take longer or shorter
+        let runs: HashSet<Vec<String>> = (0..8).map(|seed| describe(&lossy(seed).0)).collect();
+        assert!(runs.len() > 1);
+    }
+
+    #[test]
+    fn a_link_that_loses_every_table_leaves_stale_routes() {
+        let (world, _) = run_scenario("lost_link", r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "B", b = "C", weight = 1, loss = 1.0 },
+            ]
+
+            [[steps]]
+            operations = [{ op = "change_weight", a = "A", b = "B", weight = 5 }]
+        "#);
+
+        // Operations are seen at once, after that B only has C's table from before the change
+        assert!(world.nodes[1].dv[0].same_route(&DVValue::Distance(3, 2), 0));
+        assert!(world.nodes[2].dv[0].same_route(&DVValue::Distance(6, 1), 0));
+        assert_eq!(world.route_check_report(), vec!("wrong route at t=4: B reaches A at 3, the shortest path costs 5"));
+    }
+
+    #[test]
+    fn sequential_updates_carry_news_down_a_line_in_one_round() {
+        let line = |options: &str| run_scenario("line_order", &format!(r#"
+            nodes = ["A", "B", "C", "D", "E"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+                {{ a = "C", b = "D", weight = 1 }},
+                {{ a = "D", b = "E", weight = 1 }},
+            ]
+
+            [options]
+            {options}
+
+            [[steps]]
+            operations = [{{ op = "change_weight", a = "A", b = "B", weight = 3 }}]
+        "#));
+        let rounds = |world: &World<u32>| world.runs().iter().map(|r| r.rounds()).collect::<Vec<u32>>();
+
+        let (world, _) = line(r#"update_order = "simultaneous""#);
+        assert_eq!(rounds(&world), vec!(4, 4));
+
+        // B sees A's new table in the same generation, C sees B's and so on down to E
+        let (world, _) = line(r#"update_order = "sequential""#);
+        assert_eq!(rounds(&world), vec!(4, 2));
+        assert_eq!(world.route_check_report(), vec!("routes match the shortest paths at t=4, t=6"));
+
+        // Against the direction the news travels it's no faster than updating all at once
+        let (world, _) = line(r#"update_order = "sequential"
+            order = ["E", "D", "C", "B"]"#);
+        assert_eq!(rounds(&world), vec!(4, 4));
+    }
+
+    #[test]
+    fn path_vector_refuses_paths_through_itself() {
+        let line = |steps: &str| run_scenario("path_vector", &format!(r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+            ]
+
+            [options]
+            protocol = "path_vector"
+            {steps}
+        "#));
+
+        let (world, _) = line("");
+        assert!(matches!(&world.nodes[0].dv[2], DVValue::Path(2, path) if *path == vec!(1, 2)));
+
+        // B won't take A's path to C since it already goes through B, so nobody counts up
+        let (world, convergence) = line(r#"
+            [[steps]]
+            operations = [{ op = "remove_link", a = "B", b = "C" }]
+        "#);
+        assert_eq!(convergence, Convergence::Converged);
+        assert!(world.counting_report().is_empty());
+        assert!(world.loop_counts().is_empty());
+        assert!(matches!(world.nodes[0].dv[2], DVValue::Infinity));
+        assert!(matches!(world.nodes[1].dv[2], DVValue::Infinity));
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cost::{Cost, DVValue};
+use crate::metric::Metric;
+use super::World;
+
+/// How a count to infinity ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountOutcome {
+    /// The cost reached infinity, so the destination is unreachable
+    Unreachable,
+    /// The cycle broke on a real route
+    Settled,
+    /// The run stopped while the cost was still going up
+    Unfinished,
+}
+
+/// A destination whose cost keeps going up around a cycle of next hops.
+#[derive(Debug, Clone)]
+pub struct CountToInfinity {
+    destination: usize,
+    // Every node that was part of the cycle, sorted
+    nodes: Vec<usize>,
+    started: u32,
+    // Generations the cost went up in
+    rounds: u32,
+    outcome: Option<CountOutcome>
+}
+
+/// Counting that only went up once could just be a route getting worse.
+const MIN_COUNTING_ROUNDS: u32 = 2;
+
+impl CountToInfinity {
+    fn is_reported(&self) -> bool {
+        self.rounds >= MIN_COUNTING_ROUNDS
+    }
+
+    pub fn describe(&self, names: &BTreeMap<usize, String>) -> String {
+        let nodes: Vec<&str> = self.nodes.iter().map(|n| names.get(n).unwrap().as_str()).collect();
+
+        let mut description = format!(
+            "count to infinity towards {} through {} from t={} for {} round{}",
+            names.get(&self.destination).unwrap(),
+            nodes.join(", "),
+            self.started,
+            self.rounds,
+            if self.rounds == 1 { "" } else { "s" }
+        );
+
+        match self.outcome {
+            Some(CountOutcome::Unreachable) => description += ", ended unreachable",
+            Some(CountOutcome::Settled) => description += ", ended on a real route",
+            Some(CountOutcome::Unfinished) => description += ", still counting when the run stopped",
+            None => {}
+        }
+
+        description
+    }
+}
+
+/// Packets for `destination` going around `nodes` forever, each node forwarding to the next.
+#[derive(Debug, Clone)]
+pub struct ForwardingLoop {
+    destination: usize,
+    // Starts at the lowest node
+    nodes: Vec<usize>
+}
+
+impl ForwardingLoop {
+    pub fn contains(&self, node: usize, destination: usize) -> bool {
+        self.destination == destination && self.nodes.contains(&node)
+    }
+
+    pub fn describe(&self, names: &BTreeMap<usize, String>) -> String {
+        let mut path: Vec<&str> = self.nodes.iter().map(|n| names.get(n).unwrap().as_str()).collect();
+        path.push(names.get(&self.nodes[0]).unwrap().as_str());
+
+        format!(
+            "forwarding loop towards {}: {}",
+            names.get(&self.destination).unwrap(),
+            path.join("&#8594;")
+        )
+    }
+}
+
+/// The next hops `dv` sends packets for `destination` to, more than one for multipath routes.
+fn next_hops<W: Metric>(dv: &[DVValue<W>], destination: usize) -> Vec<usize> {
+    dv.get(destination).map_or(Vec::new(), |v| v.next_hops(destination))
+}
+
+/// Follows every next hop from `node` depth first, a next hop that's already on `path`
+/// closes a cycle. `state` is 0 for nodes not visited yet, 1 on the path being followed
+/// and 2 when done.
+fn follow_next_hops<F: Fn(usize) -> bool>(
+    hops: &HashMap<usize, Vec<usize>>,
+    skip: &F,
+    node: usize,
+    state: &mut HashMap<usize, u8>,
+    path: &mut Vec<usize>,
+    cycles: &mut Vec<Vec<usize>>
+) {
+    if skip(node) || !hops.contains_key(&node) {
+        return;
+    }
+
+    match state.get(&node).copied().unwrap_or(0) {
+        0 => {
+            state.insert(node, 1);
+            path.push(node);
+
+            for next in hops.get(&node).unwrap() {
+                follow_next_hops(hops, skip, *next, state, path, cycles);
+            }
+
+            path.pop();
+            state.insert(node, 2);
+        }
+        1 => {
+            let position = path.iter().position(|n| *n == node).unwrap();
+            let mut cycle = path[position..].to_vec();
+            let lowest = cycle.iter().enumerate().min_by_key(|(_, n)| **n).map(|(i, _)| i).unwrap();
+            cycle.rotate_left(lowest);
+            cycles.push(cycle);
+        }
+        _ => {}
+    }
+}
+
+/// Every cycle through the next hops in `hops`, each starting at its lowest node.
+fn cycles<F: Fn(usize) -> bool>(hops: &HashMap<usize, Vec<usize>>, skip: F) -> Vec<Vec<usize>> {
+    let mut nodes: Vec<usize> = hops.keys().copied().collect();
+    nodes.sort();
+
+    let mut state: HashMap<usize, u8> = HashMap::new();
+    let mut cycles = Vec::new();
+
+    for start in nodes {
+        follow_next_hops(hops, &skip, start, &mut state, &mut Vec::new(), &mut cycles);
+    }
+
+    cycles
+}
+
+/// Every cycle in the next hops towards `destination`, each starting at its lowest node.
+/// Multipath routes are followed down every branch. Nodes that `skip` returns true for
+/// don't forward anything.
+pub fn next_hop_cycles<W: Metric, F: Fn(usize) -> bool>(
+    dvs: &HashMap<usize, Vec<DVValue<W>>>,
+    destination: usize,
+    skip: F
+) -> Vec<Vec<usize>> {
+    let hops = dvs.iter().map(|(node, dv)| (*node, next_hops(dv, destination))).collect();
+
+    cycles(&hops, skip)
+}
+
+impl<W: Metric> World<W> {
+    /// Every forwarding loop in `dvs`, found by following the next hops from every
+    /// node towards every destination. Nodes that are down don't forward.
+    pub(super) fn forwarding_loops(&self, dvs: &HashMap<usize, Vec<DVValue<W>>>) -> Vec<ForwardingLoop> {
+        let mut loops = Vec::new();
+
+        for destination in 0..self.nodes.len() {
+            for nodes in next_hop_cycles(dvs, destination, |n| self.nodes[n].is_down()) {
+                loops.push(ForwardingLoop { destination, nodes });
+            }
+        }
+
+        loops
+    }
+
+    /// How many forwarding loops every generation had, for the generations that had any.
+    pub fn loop_counts(&self) -> &[(u32, usize)] {
+        &self.loop_counts
+    }
+
+    /// Follows the counting to infinity from the current tables to `new_dvs`, computed at
+    /// `time`. Returns every episode known afterwards, and the html warnings for the page.
+    pub(super) fn track_counting(
+        &self,
+        new_dvs: &HashMap<usize, Vec<DVValue<W>>>,
+        time: u32
+    ) -> (Vec<CountToInfinity>, Vec<String>) {
+        let names = self.node_names();
+        let mut episodes = self.counting.clone();
+        let mut continued: Vec<usize> = Vec::new();
+        let mut rose: Vec<usize> = Vec::new();
+
+        for destination in 0..self.nodes.len() {
+            // Two nodes that count up through each other can take turns pointing at the
+            // other one, so the cycle only shows with the old and the new next hops together
+            let hops: HashMap<usize, Vec<usize>> = new_dvs
+                .iter()
+                .map(|(node, dv)| {
+                    let mut hops = next_hops(dv, destination);
+                    hops.extend(next_hops(&self.nodes[*node].dv, destination));
+                    hops.sort();
+                    hops.dedup();
+
+                    (*node, hops)
+                })
+                .collect();
+
+            for cycle in cycles(&hops, |n| self.nodes[n].is_down()) {
+                let rising = cycle.iter().any(|n| {
+                    let old: Cost<W> = (&self.nodes[*n].dv[destination]).into();
+                    let new: Cost<W> = (&new_dvs.get(n).unwrap()[destination]).into();
+
+                    new > old
+                });
+
+                let ongoing = episodes.iter().position(|e|
+                    e.outcome.is_none() &&
+                        e.destination == destination &&
+                        e.nodes.iter().any(|n| cycle.contains(n))
+                );
+
+                match ongoing {
+                    Some(index) => {
+                        let episode = &mut episodes[index];
+
+                        for node in &cycle {
+                            if !episode.nodes.contains(node) {
+                                episode.nodes.push(*node);
+                            }
+                        }
+                        episode.nodes.sort();
+
+                        // Several cycles can make up one count, it still only goes up once a generation
+                        if rising && !rose.contains(&index) {
+                            episode.rounds += 1;
+                            rose.push(index);
+                        }
+
+                        continued.push(index);
+                    }
+                    None if rising => {
+                        let mut nodes = cycle.clone();
+                        nodes.sort();
+
+                        episodes.push(CountToInfinity {
+                            destination,
+                            nodes,
+                            started: time,
+                            rounds: 1,
+                            outcome: None
+                        });
+
+                        continued.push(episodes.len() - 1);
+                        rose.push(episodes.len() - 1);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut kept = Vec::new();
+
+        for (index, mut episode) in episodes.into_iter().enumerate() {
+            if episode.outcome.is_none() {
+                if continued.contains(&index) {
+                    if episode.is_reported() {
+                        warnings.push(episode.describe(&names));
+                    }
+                } else if episode.is_reported() {
+                    // The cycle is gone, so the counting is over. It breaks where the cost
+                    // reached infinity, or where a node found a real route
+                    let unreachable = episode.nodes
+                        .iter()
+                        .any(|n| new_dvs.get(n).unwrap()[episode.destination] == DVValue::Infinity);
+
+                    episode.outcome = Some(if unreachable { CountOutcome::Unreachable } else { CountOutcome::Settled });
+                    warnings.push(episode.describe(&names));
+                } else {
+                    // A cost that only went up once was just a route getting worse
+                    continue;
+                }
+            }
+
+            kept.push(episode);
+        }
+
+        (kept, warnings)
+    }
+
+    /// Every count to infinity so far that went on for more than a round, marking
+    /// the ones that haven't ended as unfinished.
+    pub fn counting_report(&self) -> Vec<String> {
+        let names = self.node_names();
+
+        self.counting
+            .iter()
+            .filter(|e| e.is_reported())
+            .map(|e| {
+                let mut episode = e.clone();
+                if episode.outcome.is_none() {
+                    episode.outcome = Some(CountOutcome::Unfinished);
+                }
+
+                episode.describe(&names)
+            })
+            .collect()
+    }
+}
+
+/// The html for the warnings at the top of a page.
+pub fn warning_banner(warnings: &[String]) -> String {
+    if warnings.is_empty() {
+        return String::new();
+    }
+
+    let mut banner = String::from("<div class=\"warning\">\n");
+    for warning in warnings {
+        banner += format!("\t<div>{}</div>\n", warning).as_str();
+    }
+    banner += "</div>\n";
+
+    banner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::run_scenario;
+
+    fn tables(routes: Vec<DVValue<u32>>) -> HashMap<usize, Vec<DVValue<u32>>> {
+        // Only the routes towards 0 matter
+        routes.into_iter().enumerate().map(|(node, route)| (node, vec!(route))).collect()
+    }
+
+    #[test]
+    fn next_hop_cycles_follow_every_multipath_branch() {
+        // 1 sends some packets straight to 0, and the others around 1, 2, 3
+        let dvs = tables(vec!(
+            DVValue::SameNode,
+            DVValue::Multipath(2, vec!(0, 2)),
+            DVValue::Distance(1, 3),
+            DVValue::Distance(1, 1)
+        ));
+
+        assert_eq!(next_hop_cycles(&dvs, 0, |_| false), vec!(vec!(1, 2, 3)));
+    }
+
+    #[test]
+    fn next_hop_cycles_find_a_cycle_on_each_branch() {
+        let dvs = tables(vec!(
+            DVValue::SameNode,
+            DVValue::Multipath(2, vec!(2, 3)),
+            DVValue::Distance(1, 1),
+            DVValue::Distance(1, 1)
+        ));
+
+        assert_eq!(next_hop_cycles(&dvs, 0, |_| false), vec!(vec!(1, 2), vec!(1, 3)));
+        // A node that doesn't forward breaks its cycle
+        assert_eq!(next_hop_cycles(&dvs, 0, |n| n == 3), vec!(vec!(1, 2)));
+    }
+
+    #[test]
+    fn next_hop_cycles_ignore_branches_that_meet_again() {
+        // Both branches of 1 end up at 3, which isn't a cycle
+        let dvs = tables(vec!(
+            DVValue::SameNode,
+            DVValue::Multipath(2, vec!(2, 4)),
+            DVValue::Distance(1, 3),
+            DVValue::DirectDistance(1),
+            DVValue::Distance(1, 3)
+        ));
+
+        assert!(next_hop_cycles(&dvs, 0, |_| false).is_empty());
+    }
+
+    #[test]
+    fn counting_to_infinity_between_two_nodes_taking_turns() {
+        // A and B point at each other in alternate generations once C is gone
+        let scenario = |max_metric: &str| format!(r#"
+            nodes = ["A", "B", "C"]
+            links = [
+                {{ a = "A", b = "B", weight = 1 }},
+                {{ a = "B", b = "C", weight = 1 }},
+            ]
+
+            [options]
+            max_generations = 20
+            {}
+
+            [[steps]]
+            operations = [{{ op = "remove_link", a = "B", b = "C" }}]
+        "#, max_metric);
+
+        let (world, _) = run_scenario("counting_unfinished", scenario("").as_str());
+        assert_eq!(
+            world.counting_report(),
+            vec!("count to infinity towards C through A, B from t=3 for 20 rounds, still counting when the run stopped")
+        );
+
+        let (world, _) = run_scenario("counting_unreachable", scenario("max_metric = 16").as_str());
+        assert_eq!(
+            world.counting_report(),
+            vec!("count to infinity towards C through A, B from t=3 for 13 rounds, ended unreachable")
+        );
+    }
+
+    #[test]
+    fn forwarding_loops_are_counted_every_generation() {
+        let (mut world, _) = run_scenario("forwarding_loops", r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 2 },
+                { a = "A", b = "D", weight = 10 },
+                { a = "B", b = "C", weight = 1 },
+                { a = "B", b = "D", weight = 20 },
+                { a = "C", b = "D", weight = 2 },
+            ]
+
+            [[steps]]
+            operations = [{ op = "change_weight", a = "C", b = "D", weight = 80 }]
+        "#);
+
+        // One loop towards D in every generation the nodes spend counting up
+        assert_eq!(world.loop_counts(), [(4, 1), (5, 1), (6, 1), (7, 1), (8, 1), (9, 1), (11, 1)]);
+        assert!(world.forwarding_loops(&world.copy_dvs()).is_empty());
+
+        world.nodes[1].dv[3] = DVValue::Distance(5, 2);
+        world.nodes[2].dv[3] = DVValue::Distance(4, 1);
+        let loops = world.forwarding_loops(&world.copy_dvs());
+        let described: Vec<String> = loops.iter().map(|l| l.describe(&world.node_names())).collect();
+        assert_eq!(described, vec!("forwarding loop towards D: B&#8594;C&#8594;B"));
+    }
+}
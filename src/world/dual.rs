@@ -0,0 +1,545 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::cost::{same_routes, Cost, DVValue};
+use crate::metric::Metric;
+use crate::repr::{DistanceCalculationLine, HtmlFiles, HtmlFormula, TermNote};
+use super::analysis::{warning_banner, ForwardingLoop};
+use super::events::Delays;
+use super::{NewState, Node, World};
+
+/// A query or a reply about a destination. EIGRP sends them reliably, next to the updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum DualMessage {
+    Query(usize),
+    Reply(usize),
+}
+
+impl DualMessage {
+    fn describe(&self, names: &BTreeMap<usize, String>) -> String {
+        match self {
+            DualMessage::Query(target) => format!("query for {}", names.get(target).unwrap()),
+            DualMessage::Reply(target) => format!("reply for {}", names.get(target).unwrap())
+        }
+    }
+}
+
+/// A destination in a diffusing computation, its route is frozen until every neighbor replied.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Diffusing {
+    waiting: BTreeSet<usize>,
+    // The successor whose query started the computation, it gets a reply once it ends
+    reply_to: Option<usize>
+}
+
+/// What DUAL keeps about a destination besides the route itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DualRoute<W: Metric> {
+    // The lowest distance since the destination was last active
+    feasible_distance: Cost<W>,
+    // Neighbors other than the successor whose reported distance is below the feasible distance
+    feasible_successors: Vec<usize>,
+    active: Option<Diffusing>
+}
+
+impl<W: Metric> Default for DualRoute<W> {
+    fn default() -> Self {
+        DualRoute { feasible_distance: Cost::Infinity, feasible_successors: Vec::new(), active: None }
+    }
+}
+
+/// The DUAL state of a node, like the EIGRP topology table. The reported distances are
+/// the neighbor rows every node already keeps.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dual<W: Metric> {
+    // Destinations that never had a route are passive with an infinite feasible distance
+    routes: BTreeMap<usize, DualRoute<W>>,
+    // The queries and replies to send every neighbor next round
+    outbox: BTreeMap<usize, Vec<DualMessage>>
+}
+
+impl<W: Metric> Default for Dual<W> {
+    fn default() -> Self {
+        Dual { routes: BTreeMap::new(), outbox: BTreeMap::new() }
+    }
+}
+
+impl<W: Metric> Dual<W> {
+    fn send(&mut self, to: usize, message: DualMessage) {
+        self.outbox.entry(to).or_default().push(message);
+    }
+}
+
+/// A node after a DUAL computation: its routes, its new DUAL state and how it got there.
+#[derive(Debug, Clone)]
+pub(super) struct DualComputed<W: Metric> {
+    dv: Vec<DVValue<W>>,
+    dual: Dual<W>,
+    lines: Vec<String>
+}
+
+/// A neighbor as a way to a destination: its reported distance, the distance through
+/// it and why its advertisement isn't its real distance.
+struct Candidate<W: Metric> {
+    neighbor: usize,
+    reported: Cost<W>,
+    distance: Cost<W>,
+    note: Option<TermNote>
+}
+
+fn list(nodes: &[usize], names: &BTreeMap<usize, String>) -> String {
+    nodes.iter().map(|n| names.get(n).unwrap().as_str()).collect::<Vec<_>>().join(", ")
+}
+
+impl<W: Metric> World<W> {
+    /// Every neighbor of `node` as a way to `target`.
+    fn candidates(&self, node: &Node<W>, target: usize) -> Vec<Candidate<W>> {
+        let max_metric = self.options.max_metric.as_ref();
+
+        node.neighbors.iter().map(|neighbor| {
+            let direct = Cost::Value(neighbor.direct_cost.to_owned());
+
+            if neighbor.index == target {
+                Candidate { neighbor: neighbor.index, reported: Cost::Zero, distance: direct.capped(max_metric), note: None }
+            } else {
+                let (reported, note) = self.advertised(neighbor, node.index, target);
+                let distance = direct.add_capped(reported.clone(), max_metric);
+
+                Candidate { neighbor: neighbor.index, reported, distance, note }
+            }
+        }).collect()
+    }
+
+    /// The formula over `candidates`, leaving out the ones that aren't feasible unless
+    /// `feasible_distance` is None.
+    fn dual_formula(
+        &self,
+        node: &Node<W>,
+        target: usize,
+        candidates: &[Candidate<W>],
+        feasible_distance: Option<&Cost<W>>
+    ) -> HtmlFormula<W> {
+        let mut formula = HtmlFormula::new(target, node.index, self.options.max_metric.clone());
+
+        for candidate in candidates {
+            let direct_cost = node.neighbor(candidate.neighbor).unwrap().direct_cost.to_owned();
+            let feasible = feasible_distance.is_none_or(|fd| candidate.reported < *fd);
+            let note = match candidate.note {
+                None if !feasible && candidate.distance != Cost::Infinity => Some(TermNote::NotFeasible),
+                note => note
+            };
+
+            if candidate.neighbor == target {
+                formula.add_direct(candidate.neighbor, node.index, Cost::Value(direct_cost), note);
+            } else {
+                formula.add_indirect(
+                    candidate.neighbor,
+                    node.index,
+                    direct_cost,
+                    target,
+                    candidate.neighbor,
+                    candidate.reported.clone(),
+                    note
+                );
+            }
+        }
+
+        formula
+    }
+
+    /// Runs DUAL on `node` for every destination, with the queries and replies that just
+    /// arrived in `inbox`.
+    fn compute_dual(
+        &self,
+        node: &Node<W>,
+        inbox: &[(usize, DualMessage)],
+        names: &BTreeMap<usize, String>
+    ) -> DualComputed<W> {
+        let mut dv = Vec::new();
+        let mut dual = Dual { routes: BTreeMap::new(), outbox: node.dual.outbox.clone() };
+        let mut lines = Vec::new();
+
+        for (target, v_old) in node.dv.iter().enumerate() {
+            let queries: Vec<usize> = inbox.iter().filter(|(_, m)| *m == DualMessage::Query(target)).map(|(from, _)| *from).collect();
+            let replies: Vec<usize> = inbox.iter().filter(|(_, m)| *m == DualMessage::Reply(target)).map(|(from, _)| *from).collect();
+
+            if target == node.index {
+                for from in queries {
+                    dual.send(from, DualMessage::Reply(target));
+                }
+
+                dv.push(DVValue::SameNode);
+                continue;
+            }
+
+            let mut route = node.dual.routes.get(&target).cloned().unwrap_or_default();
+            let candidates = self.candidates(node, target);
+            let successor = v_old.next_hop(target);
+            let through_successor = candidates
+                .iter()
+                .find(|c| Some(c.neighbor) == successor)
+                .map_or(DVValue::Infinity, |c| c.distance.to_dv_value(c.neighbor, c.neighbor == target));
+            let name = format!("d<sub>{}</sub>({})", node.name, names.get(&target).unwrap());
+
+            // Queries are answered right away, except the successor's when it makes this one go active
+            let mut answer: Vec<usize> = queries.clone();
+            let mut line;
+            let v;
+
+            match route.active.take() {
+                Some(mut diffusing) => {
+                    diffusing.waiting.retain(|n| !replies.contains(n) && node.neighbor(*n).is_some());
+
+                    if diffusing.waiting.is_empty() {
+                        let formula = self.dual_formula(node, target, &candidates, None);
+                        let (best, tie) = self.break_tie(node, target, &formula, names);
+                        v = best;
+                        route.feasible_distance = (&v).into();
+                        line = format!("{}{}, every reply is in, passive again", formula.render(names), tie.unwrap_or_default());
+                        answer.extend(diffusing.reply_to);
+                    } else {
+                        v = through_successor;
+                        line = format!(
+                            "{} <span class=\"note\">active</span>, waiting for {}",
+                            name,
+                            list(&diffusing.waiting.iter().copied().collect::<Vec<_>>(), names)
+                        );
+                        route.active = Some(diffusing);
+                    }
+                }
+                None => {
+                    let formula = self.dual_formula(node, target, &candidates, Some(&route.feasible_distance));
+                    let (best, tie) = self.break_tie(node, target, &formula, names);
+                    let best_cost: Cost<W> = (&best).into();
+                    let shortest = candidates.iter().map(|c| c.distance.clone()).min().unwrap_or(Cost::Infinity);
+
+                    line = formula.render(names) + tie.unwrap_or_default().as_str();
+
+                    if best_cost != Cost::Infinity && best_cost == shortest {
+                        route.feasible_distance = route.feasible_distance.clone().min(best_cost);
+                        v = best;
+                    } else if shortest == Cost::Infinity && route.feasible_distance == Cost::Infinity {
+                        v = DVValue::Infinity;
+                    } else {
+                        // No feasible successor, the neighbors have to be asked
+                        let reply_to = successor.filter(|s| queries.contains(s));
+                        let waiting: BTreeSet<usize> = node.neighbors
+                            .iter()
+                            .map(|n| n.index)
+                            .filter(|n| Some(*n) != reply_to)
+                            .collect();
+
+                        answer.retain(|n| Some(*n) != reply_to);
+
+                        if waiting.is_empty() {
+                            let formula = self.dual_formula(node, target, &candidates, None);
+                            let (best, tie) = self.break_tie(node, target, &formula, names);
+                            v = best;
+                            route.feasible_distance = (&v).into();
+                            line += format!("{}, no feasible successor and nobody to ask", tie.unwrap_or_default()).as_str();
+                            answer.extend(reply_to);
+                        } else {
+                            for neighbor in &waiting {
+                                dual.send(*neighbor, DualMessage::Query(target));
+                            }
+
+                            v = through_successor;
+                            line += format!(
+                                ", no feasible successor, <span class=\"note\">active</span>, queries to {}",
+                                list(&waiting.iter().copied().collect::<Vec<_>>(), names)
+                            ).as_str();
+                            route.active = Some(Diffusing { waiting, reply_to });
+                        }
+                    }
+                }
+            }
+
+            if route.active.is_none() {
+                let via = v.next_hop(target);
+                route.feasible_successors = candidates
+                    .iter()
+                    .filter(|c| Some(c.neighbor) != via && c.distance != Cost::Infinity && c.reported < route.feasible_distance)
+                    .map(|c| c.neighbor)
+                    .collect();
+
+                line += format!(", FD={}", route.feasible_distance.write_html()).as_str();
+                if !route.feasible_successors.is_empty() {
+                    line += format!(", feasible successors {}", list(&route.feasible_successors, names)).as_str();
+                }
+            } else {
+                route.feasible_successors.clear();
+            }
+
+            if !answer.is_empty() {
+                line += format!(", replies to {}", list(&answer, names)).as_str();
+            }
+
+            for from in answer {
+                dual.send(from, DualMessage::Reply(target));
+            }
+
+            if route != DualRoute::default() {
+                dual.routes.insert(target, route);
+            }
+
+            dv.push(v);
+            lines.push(line);
+        }
+
+        DualComputed { dv, dual, lines }
+    }
+
+    /// Prints a node for DUAL: its routes, its feasible distances with the destinations that
+    /// are active, and its feasible successors.
+    pub(super) fn print_dual_node<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        computed: Option<&DualComputed<W>>,
+        removed: &[(usize, usize)],
+        loops: &[ForwardingLoop]
+    ) -> Result<(), Box<dyn Error>> {
+        let names = self.node_names();
+        let dual = computed.map_or(&node.dual, |c| &c.dual);
+        self.print_routes(writer, node, computed.map(|c| &c.dv), &node.timers, loops)?;
+
+        writeln!(writer, "\t<tr>\n\t\t<th title=\"feasible distance\">FD</th>")?;
+        for index in 0..self.nodes.len() {
+            match dual.routes.get(&index) {
+                Some(DualRoute { active: Some(diffusing), feasible_distance, .. }) => writeln!(
+                    writer,
+                    "\t\t<td class=\"active\" title=\"active, waiting for {}\">{} A</td>",
+                    list(&diffusing.waiting.iter().copied().collect::<Vec<_>>(), &names),
+                    feasible_distance.write_html()
+                )?,
+                Some(route) => writeln!(writer, "\t\t<td>{}</td>", route.feasible_distance.write_html())?,
+                None => writeln!(writer, "\t\t<td></td>")?
+            }
+        }
+        writeln!(writer, "\t</tr>")?;
+
+        writeln!(writer, "\t<tr>\n\t\t<th title=\"feasible successors\">FS</th>")?;
+        for index in 0..self.nodes.len() {
+            let successors = dual.routes.get(&index).map_or(Vec::new(), |r| r.feasible_successors.clone());
+            writeln!(writer, "\t\t<td>{}</td>", list(&successors, &names))?;
+        }
+        writeln!(writer, "\t</tr>")?;
+
+        self.close_table(writer, node, removed)?;
+
+        if let Some(computed) = computed {
+            writeln!(writer, "<div class=\"details\">")?;
+            for line in &computed.lines {
+                writeln!(writer, "\t<div>{}</div>", line)?;
+            }
+            writeln!(writer, "</div>")?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a round of DUAL. Queries and replies queued last round arrive first, then every
+    /// node that heard something recomputes. A destination whose shortest way through the
+    /// neighbors fails the feasibility condition goes active and queries every neighbor,
+    /// and stays on its old route until they all replied. Tables that changed go out at
+    /// the end of the round.
+    ///
+    /// EIGRP sends everything reliably, so nothing is lost on unreliable links.
+    pub(super) fn run_dual(&self, html_factory: &mut HtmlFiles) -> Result<NewState<W>, Box<dyn Error>> {
+        let names = self.node_names();
+        let mut world = self.clone();
+        let mut trace: Vec<String> = Vec::new();
+        // receiver -> (sender, message)
+        let mut inbox: BTreeMap<usize, Vec<(usize, DualMessage)>> = BTreeMap::new();
+
+        world.generation += 1;
+
+        for node in world.nodes.iter_mut().filter(|n| !n.is_down() && !n.silent) {
+            for (neighbor, messages) in std::mem::take(&mut node.dual.outbox) {
+                // The link may be gone since they were queued
+                if node.neighbor(neighbor).is_none() || self.nodes[neighbor].is_down() {
+                    continue;
+                }
+
+                trace.push(format!(
+                    "{}&#8594;{}: {}",
+                    node.name,
+                    names.get(&neighbor).unwrap(),
+                    messages.iter().map(|m| m.describe(&names)).collect::<Vec<_>>().join(", ")
+                ));
+                inbox.entry(neighbor).or_default().extend(messages.into_iter().map(|m| (node.index, m)));
+            }
+        }
+
+        world.messages += inbox.values().map(|m| m.len() as u32).sum::<u32>();
+
+        let mut computed = BTreeMap::new();
+        for node in world.nodes.iter().filter(|n| !n.is_down() && (n.has_updates || inbox.contains_key(&n.index))) {
+            let messages = inbox.get(&node.index).map_or(&[][..], |m| m.as_slice());
+            computed.insert(node.index, world.compute_dual(node, messages, &names));
+        }
+
+        let mut new_dvs = world.copy_dvs();
+        for (index, c) in &computed {
+            new_dvs.insert(*index, c.dv.clone());
+        }
+
+        let loops = world.forwarding_loops(&new_dvs);
+        let (counting, mut warnings) = world.track_counting(&new_dvs, world.generation);
+        warnings.extend(loops.iter().map(|l| l.describe(&names)));
+
+        let mut writer: Vec<u8> = Vec::new();
+        if !trace.is_empty() {
+            writeln!(writer, "<div class=\"events\">")?;
+            for line in &trace {
+                writeln!(writer, "\t<div>{}</div>", line)?;
+            }
+            writeln!(writer, "</div>")?;
+        }
+        write!(writer, "{}", world.tie_break_html())?;
+
+        for node in &world.nodes {
+            world.print_dual_node(&mut writer, node, computed.get(&node.index), &[], &loops)?;
+        }
+
+        html_factory.create(|w| {
+            writeln!(w, "<h2>t={}</h2>", world.generation)?;
+            w.write_all(warning_banner(&warnings).as_bytes())?;
+            w.write_all(writer.as_slice())?;
+            Ok(())
+        })?;
+
+        let mut updated: HashSet<usize> = HashSet::new();
+        let mut changed = !trace.is_empty();
+
+        for (index, c) in computed {
+            let node = &mut world.nodes[index];
+
+            if !same_routes(&node.dv, &c.dv) {
+                updated.insert(index);
+            }
+            changed |= node.dual != c.dual;
+
+            node.dv = c.dv;
+            node.dual = c.dual;
+        }
+
+        if !changed && updated.is_empty() {
+            return Ok(NewState::NotChanged);
+        }
+
+        for node in world.nodes.iter_mut() {
+            node.has_updates = false;
+        }
+
+        // The updates, a silent node keeps its changes to itself
+        for index in updated {
+            if world.nodes[index].silent {
+                continue;
+            }
+
+            let dv = world.nodes[index].dv.clone();
+
+            for other in world.nodes.iter_mut() {
+                for neighbor in other.neighbors.iter_mut().filter(|n| n.index == index) {
+                    neighbor.dv = dv.clone();
+                    other.has_updates = true;
+                    world.messages += 1;
+                }
+            }
+        }
+
+        world.counting = counting;
+        if !loops.is_empty() {
+            world.loop_counts.push((world.generation, loops.len()));
+        }
+
+        let generation = world.generation;
+        world.move_packets(generation, &Delays::new());
+
+        Ok(NewState::Changed(Box::new(world)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Neighbor;
+
+    const D: usize = 3;
+
+    /// A reaches D through B at 2 with that as its feasible distance. B and C report
+    /// `b_reports` and `c_reports`, the link to C costs `c_cost`.
+    fn world(b_reports: DVValue<u32>, c_cost: u32, c_reports: DVValue<u32>) -> World<u32> {
+        let mut world: World<u32> = World::new(vec!("A", "B", "C", "D"));
+        let neighbor = |index, direct_cost, reports| {
+            let mut dv = vec!(DVValue::Infinity; 4);
+            dv[index] = DVValue::SameNode;
+            dv[D] = reports;
+            Neighbor { index, direct_cost, dv, age: 0 }
+        };
+
+        let a = &mut world.nodes[0];
+        a.neighbors = vec!(neighbor(1, 1, b_reports), neighbor(2, c_cost, c_reports));
+        a.dv = vec!(DVValue::SameNode, DVValue::DirectDistance(1), DVValue::DirectDistance(c_cost), DVValue::Distance(2, 1));
+        a.dual.routes.insert(D, DualRoute { feasible_distance: Cost::Value(2), ..DualRoute::default() });
+
+        world
+    }
+
+    fn compute(world: &World<u32>, inbox: &[(usize, DualMessage)]) -> DualComputed<u32> {
+        world.compute_dual(&world.nodes[0], inbox, &world.node_names())
+    }
+
+    #[test]
+    fn feasible_successors_report_less_than_the_feasible_distance() {
+        let feasible = world(DVValue::DirectDistance(1), 2, DVValue::DirectDistance(1));
+        let computed = compute(&feasible, &[]);
+        assert_eq!(computed.dual.routes[&D].feasible_successors, vec!(2));
+
+        // Reporting exactly the feasible distance isn't enough, C might route through A
+        let not_feasible = world(DVValue::DirectDistance(1), 1, DVValue::Distance(2, 0));
+        let computed = compute(&not_feasible, &[]);
+        assert!(computed.dual.routes[&D].feasible_successors.is_empty());
+    }
+
+    #[test]
+    fn a_feasible_successor_takes_over_without_queries() {
+        let world = world(DVValue::Infinity, 2, DVValue::DirectDistance(1));
+        let computed = compute(&world, &[]);
+        let route = &computed.dual.routes[&D];
+
+        assert!(computed.dv[D].same_route(&DVValue::Distance(3, 2), D));
+        assert_eq!(route.active, None);
+        // The feasible distance only goes up once the destination was active
+        assert_eq!(route.feasible_distance, Cost::Value(2));
+        assert!(computed.dual.outbox.is_empty());
+    }
+
+    #[test]
+    fn without_a_feasible_successor_the_route_goes_active_until_every_reply_is_in() {
+        let mut world = world(DVValue::Infinity, 1, DVValue::Distance(3, 0));
+        let computed = compute(&world, &[]);
+        let route = &computed.dual.routes[&D];
+
+        assert!(matches!(computed.dv[D], DVValue::Infinity));
+        assert_eq!(route.active, Some(Diffusing { waiting: BTreeSet::from([1, 2]), reply_to: None }));
+        assert_eq!(computed.dual.outbox[&1], vec!(DualMessage::Query(D)));
+        assert_eq!(computed.dual.outbox[&2], vec!(DualMessage::Query(D)));
+
+        world.nodes[0].dv = computed.dv;
+        world.nodes[0].dual = Dual { outbox: BTreeMap::new(), ..computed.dual };
+
+        // Still waiting for C
+        let computed = compute(&world, &[(1, DualMessage::Reply(D))]);
+        assert_eq!(computed.dual.routes[&D].active, Some(Diffusing { waiting: BTreeSet::from([2]), reply_to: None }));
+
+        let computed = compute(&world, &[(1, DualMessage::Reply(D)), (2, DualMessage::Reply(D))]);
+        let route = &computed.dual.routes[&D];
+
+        assert!(computed.dv[D].same_route(&DVValue::Distance(4, 2), D));
+        assert_eq!(route.active, None);
+        assert_eq!(route.feasible_distance, Cost::Value(4));
+    }
+}
@@ -0,0 +1,403 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::error::Error;
+use std::io::Write;
+
+use crate::cost::{same_routes, DVValue};
+use crate::metric::Metric;
+use crate::options::{Options, Protocol};
+use crate::random::Random;
+use crate::repr::HtmlFiles;
+use super::analysis::warning_banner;
+use super::{same_nodes, Convergence, Node, World};
+
+/// Propagation delays of directed links, in time units. Links that aren't listed take `DEFAULT_DELAY`.
+pub type Delays = HashMap<(usize, usize), u32>;
+
+pub const DEFAULT_DELAY: u32 = 1;
+
+#[derive(Debug, Clone)]
+enum EventKind<W: Metric> {
+    /// A table sent from one node to its neighbor
+    Advertisement {
+        from: usize,
+        to: usize,
+        sent: u32,
+        dv: Vec<DVValue<W>>,
+        // Lost tables still get an event, so the page can tell
+        lost: bool,
+        duplicate: bool
+    },
+    /// A node with routes in hold-down checks them again
+    HoldDownTick(usize),
+}
+
+impl<W: Metric> EventKind<W> {
+    /// Whether two events do the same thing, when they were sent only shows on the page.
+    fn same_event(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                EventKind::Advertisement { from, to, dv, lost, duplicate, .. },
+                EventKind::Advertisement { from: from2, to: to2, dv: dv2, lost: lost2, duplicate: duplicate2, .. }
+            ) => from == from2 && to == to2 && lost == lost2 && duplicate == duplicate2 && same_routes(dv, dv2),
+            (EventKind::HoldDownTick(node), EventKind::HoldDownTick(node2)) => node == node2,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Event<W: Metric> {
+    time: u32,
+    // Keeps events that happen at the same time in the order they were scheduled
+    sequence: u64,
+    kind: EventKind<W>
+}
+
+impl<W: Metric> PartialEq for Event<W> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<W: Metric> Eq for Event<W> {}
+
+impl<W: Metric> PartialOrd for Event<W> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<W: Metric> Ord for Event<W> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.sequence).cmp(&(other.time, other.sequence))
+    }
+}
+
+struct EventQueue<W: Metric> {
+    events: BinaryHeap<Reverse<Event<W>>>,
+    sequence: u64
+}
+
+impl<W: Metric> EventQueue<W> {
+    fn new() -> Self {
+        EventQueue { events: BinaryHeap::new(), sequence: 0 }
+    }
+
+    fn schedule(&mut self, time: u32, kind: EventKind<W>) {
+        self.events.push(Reverse(Event { time, sequence: self.sequence, kind }));
+        self.sequence += 1;
+    }
+
+    fn next_time(&self) -> Option<u32> {
+        self.events.peek().map(|Reverse(event)| event.time)
+    }
+
+    /// Takes every event that happens at `time`, in scheduling order.
+    fn pop_at(&mut self, time: u32) -> Vec<Event<W>> {
+        let mut events = Vec::new();
+
+        while self.next_time() == Some(time) {
+            if let Some(Reverse(event)) = self.events.pop() {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    /// The events still to come in the order they happen, timed from `now`.
+    fn upcoming(&self, now: u32) -> Vec<(u32, EventKind<W>)> {
+        let mut events: Vec<&Event<W>> = self.events.iter().map(|Reverse(event)| event).collect();
+        events.sort();
+        events.into_iter().map(|event| (event.time - now, event.kind.clone())).collect()
+    }
+}
+
+/// Earlier states by the hash of their nodes: the time, the nodes, the events that were
+/// still to come and the random generator
+type Visits<W> = HashMap<u64, Vec<(u32, Vec<Node<W>>, Vec<(u32, EventKind<W>)>, Random)>>;
+
+/// Whether two lists of upcoming events are the same.
+fn same_upcoming<W: Metric>(a: &[(u32, EventKind<W>)], b: &[(u32, EventKind<W>)]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|((t, x), (t2, y))| t == t2 && x.same_event(y))
+}
+
+impl<W: Metric> World<W> {
+    fn delay(delays: &Delays, from: usize, to: usize) -> u32 {
+        delays.get(&(from, to)).copied().unwrap_or(DEFAULT_DELAY)
+    }
+
+    /// Puts an advertisement into the receiver's copy of the sender's table.
+    /// Returns false when the link is gone by the time it arrives.
+    fn deliver(&mut self, from: usize, to: usize, dv: &[DVValue<W>]) -> bool {
+        match self.nodes[to].neighbors.iter_mut().find(|n| n.index == from) {
+            Some(neighbor) => {
+                neighbor.dv = dv.to_vec();
+                neighbor.age = 0;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+/// Whether a run with `options` can go on the event engine, and why not.
+pub fn check_events<W>(options: &Options<W>) -> Result<(), Box<dyn Error>> {
+    if options.protocol == Protocol::LinkState {
+        return Err("link state floods in rounds, it can't run on the event engine".into());
+    }
+
+    if options.protocol == Protocol::Dual {
+        return Err("DUAL waits for replies in rounds, it can't run on the event engine".into());
+    }
+
+    if options.timers.is_some() {
+        return Err("route timers count lockstep rounds, they can't run on the event engine".into());
+    }
+
+    Ok(())
+}
+
+/// Runs `world` on the event engine until no advertisement is in flight, writing a page for
+/// every point in time something happens. The generation is used as the clock.
+///
+/// The operations that led to `world` are seen by every neighbor at once, like in the lockstep
+/// engine, after that every table takes the delay of its link to arrive. Tables in flight are
+/// part of the state, a run is oscillating when the tables and everything still in flight
+/// come back, with the same rules on random losses as `run_until_stable`. The time limit
+/// counts from the start of this run. Converged tables are checked against the shortest paths.
+pub fn run_events<W: Metric>(
+    html_factory: &mut HtmlFiles,
+    mut world: World<W>,
+    delays: &Delays
+) -> Result<(World<W>, Convergence), Box<dyn Error>> {
+    check_events(&world.options)?;
+
+    let names = world.node_names();
+    let mut queue = EventQueue::new();
+    // The neighbors react one time unit after the operations
+    let mut time = world.generation + 1;
+    let mut due: BTreeSet<usize> = world.nodes
+        .iter()
+        .filter(|n| n.has_updates && !n.is_down())
+        .map(|n| n.index)
+        .collect();
+    let mut trace: Vec<String> = vec!(String::from("the change is seen by every neighbor"));
+    let mut seen: Visits<W> = HashMap::new();
+    let mut revisited = None;
+    let started = world.generation;
+
+    loop {
+        if due.is_empty() {
+            time = match queue.next_time() {
+                Some(time) => time,
+                None => break
+            };
+
+            let upcoming = queue.upcoming(time);
+            let earlier = seen.entry(world.state_hash()).or_default();
+            let repeated: Vec<_> = earlier
+                .iter()
+                .filter(|(_, nodes, events, _)| same_nodes(nodes, &world.nodes) && same_upcoming(events, &upcoming))
+                .collect();
+            if let Some((previous, _, _, _)) = repeated.iter().find(|(_, _, _, random)| *random == world.random) {
+                let period = time - previous;
+                return Ok((world, Convergence::Oscillating(period)));
+            }
+            if let Some((previous, _, _, _)) = repeated.last() {
+                revisited = Some(time - previous);
+            }
+            earlier.push((time, world.nodes.clone(), upcoming, world.random.clone()));
+
+            // `time` is still to come, like generation `started + max_generations + 1`
+            // the lockstep engine doesn't compute anymore
+            if time - started > world.options.max_generations {
+                let convergence = revisited.map_or(Convergence::GenerationLimit, Convergence::Oscillating);
+                return Ok((world, convergence));
+            }
+
+            for event in queue.pop_at(time) {
+                match event.kind {
+                    EventKind::Advertisement { from, to, sent, lost: true, .. } => {
+                        trace.push(format!(
+                            "{}&#8594;{} sent at t={} lost",
+                            names.get(&from).unwrap(),
+                            names.get(&to).unwrap(),
+                            sent
+                        ));
+                    }
+                    EventKind::Advertisement { from, to, sent, dv, duplicate, .. } => {
+                        if world.deliver(from, to, &dv) {
+                            trace.push(format!(
+                                "{}&#8594;{} sent at t={} arrived{}",
+                                names.get(&from).unwrap(),
+                                names.get(&to).unwrap(),
+                                sent,
+                                if duplicate { " again, duplicated" } else { "" }
+                            ));
+
+                            due.insert(to);
+                        } else {
+                            trace.push(format!(
+                                "{}&#8594;{} sent at t={} lost, the link is gone",
+                                names.get(&from).unwrap(),
+                                names.get(&to).unwrap(),
+                                sent
+                            ));
+                        }
+                    }
+                    EventKind::HoldDownTick(node) => {
+                        trace.push(format!("hold-down timer of {}", names.get(&node).unwrap()));
+                        due.insert(node);
+                    }
+                }
+            }
+        }
+
+        world.generation = time;
+
+        let mut writer: Vec<u8> = Vec::new();
+        writeln!(writer, "<div class=\"events\">")?;
+        for line in &trace {
+            writeln!(writer, "\t<div>{}</div>", line)?;
+        }
+        writeln!(writer, "</div>")?;
+        write!(writer, "{}", world.tie_break_html())?;
+
+        let mut computed_nodes = BTreeMap::new();
+        for node in &world.nodes {
+            if due.contains(&node.index) && !node.is_down() {
+                computed_nodes.insert(node.index, world.compute_node(node, &names));
+            }
+        }
+
+        let mut new_dvs = world.copy_dvs();
+        for (index, computed) in &computed_nodes {
+            new_dvs.insert(*index, computed.dv.clone());
+        }
+
+        let loops = world.forwarding_loops(&new_dvs);
+
+        for node in &world.nodes {
+            match computed_nodes.get(&node.index) {
+                Some(computed) => world.print_computed(&mut writer, node, computed, &loops)?,
+                None => world.print_node(&mut writer, node, None, &node.timers, &[], &loops)?
+            }
+        }
+
+        let (counting, mut warnings) = world.track_counting(&new_dvs, time);
+        warnings.extend(loops.iter().map(|l| l.describe(&names)));
+        world.counting = counting;
+        if !loops.is_empty() {
+            world.loop_counts.push((time, loops.len()));
+        }
+
+        html_factory.create(|w| {
+            writeln!(w, "<h2>t={}</h2>", time)?;
+            w.write_all(warning_banner(&warnings).as_bytes())?;
+            w.write_all(writer.as_slice())?;
+            Ok(())
+        })?;
+
+        for (index, computed) in computed_nodes {
+            let changed = !same_routes(&computed.dv, &world.nodes[index].dv);
+            let node = &mut world.nodes[index];
+
+            node.dv = computed.dv;
+            node.timers = computed.timers;
+            node.hold_down = computed.hold_down;
+            node.has_updates = false;
+
+            // Nobody hears about changes on a silent node
+            if changed && !node.silent {
+                for neighbor in &node.neighbors {
+                    let delay = World::<W>::delay(delays, index, neighbor.index);
+                    let loss = world.losses.get(&(index, neighbor.index)).copied().unwrap_or_default();
+                    let lost = world.random.chance(loss.loss);
+                    world.messages += 1;
+
+                    queue.schedule(
+                        time + delay,
+                        EventKind::Advertisement {
+                            from: index,
+                            to: neighbor.index,
+                            sent: time,
+                            dv: node.dv.clone(),
+                            lost,
+                            duplicate: false
+                        }
+                    );
+
+                    // The copy trails the original by a time unit, and may overtake a newer table
+                    if !lost && world.random.chance(loss.duplication) {
+                        queue.schedule(
+                            time + delay + 1,
+                            EventKind::Advertisement {
+                                from: index,
+                                to: neighbor.index,
+                                sent: time,
+                                dv: node.dv.clone(),
+                                lost: false,
+                                duplicate: true
+                            }
+                        );
+                    }
+                }
+            }
+
+            if node.hold_down.iter().any(|h| h.is_some()) {
+                queue.schedule(time + 1, EventKind::HoldDownTick(index));
+            }
+        }
+
+        world.move_packets(time, delays);
+
+        due.clear();
+        trace.clear();
+    }
+
+    world.check_routes();
+
+    Ok((world, Convergence::Converged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::tests::run_scenario;
+
+    #[test]
+    fn tables_take_the_delay_of_their_link_to_arrive() {
+        let (world, convergence) = run_scenario("events_delays", r#"
+            nodes = ["A", "B", "C", "D"]
+            links = [
+                { a = "A", b = "B", weight = 1 },
+                { a = "A", b = "D", weight = 4, delay = 2 },
+                { a = "B", b = "C", weight = 7 },
+                { a = "B", b = "D", weight = 2 },
+                { a = "C", b = "D", weight = 3, delay = 4 },
+            ]
+
+            [options]
+            engine = "events"
+
+            [[steps]]
+            operations = [{ op = "remove_link", a = "B", b = "D" }]
+        "#);
+
+        assert_eq!(convergence, Convergence::Converged);
+        // The lockstep engine settles at t=3 and t=6, the slow links stretch both runs to 9
+        assert_eq!(world.route_check_report(), vec!("routes match the shortest paths at t=9, t=18"));
+        assert!(world.nodes[1].dv[3].same_route(&DVValue::Distance(5, 0), 3));
+    }
+
+    #[test]
+    fn protocols_that_need_rounds_are_rejected() {
+        let options = |protocol| Options::<u32> { protocol, ..Options::default() };
+
+        assert!(check_events(&options(Protocol::DistanceVector)).is_ok());
+        assert!(check_events(&options(Protocol::LinkState)).is_err());
+        assert!(check_events(&options(Protocol::Dual)).is_err());
+    }
+}
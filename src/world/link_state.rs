@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::cost::{Cost, DVValue};
+use crate::metric::Metric;
+use crate::repr::HtmlFiles;
+use super::analysis::{warning_banner, ForwardingLoop};
+use super::events::Delays;
+use super::{NewState, Node, World};
+
+/// A node's own links as flooded through the network, like an OSPF router LSA.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lsa<W> {
+    sequence: u32,
+    // (neighbor, cost from the origin to it), sorted by neighbor
+    links: Vec<(usize, W)>
+}
+
+/// What a node knows about the topology, and the LSAs it still has to pass on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lsdb<W> {
+    // Keyed by the node that originated them
+    lsas: BTreeMap<usize, Lsa<W>>,
+    // The origins of the LSAs to send every neighbor next round
+    outbox: BTreeMap<usize, BTreeSet<usize>>
+}
+
+impl<W> Default for Lsdb<W> {
+    fn default() -> Self {
+        Lsdb { lsas: BTreeMap::new(), outbox: BTreeMap::new() }
+    }
+}
+
+impl<W: Metric> Lsdb<W> {
+    /// The cost `origin` advertises towards `to`, as long as `to` advertises the link back.
+    fn two_way_cost(&self, origin: usize, to: usize) -> Option<&W> {
+        let back = self.lsas.get(&to).is_some_and(|lsa| lsa.links.iter().any(|(n, _)| *n == origin));
+
+        self.lsas
+            .get(&origin)
+            .and_then(|lsa| lsa.links.iter().find(|(n, _)| *n == to))
+            .map(|(_, w)| w)
+            .filter(|_| back)
+    }
+}
+
+impl<W: Metric> World<W> {
+    /// Runs SPF over the database of `node`, returning its routes and how every
+    /// destination was reached, in the order they were settled.
+    fn shortest_path_first(&self, node: &Node<W>, names: &BTreeMap<usize, String>) -> (Vec<DVValue<W>>, Vec<String>) {
+        let lsdb = &node.lsdb;
+        let max_metric = self.options.max_metric.as_ref();
+        let mut dv = vec!(DVValue::Infinity; self.nodes.len());
+        let mut lines = Vec::new();
+        // destination -> (cost, first hop, previous node)
+        let mut settled: BTreeMap<usize, (Cost<W>, usize, usize)> = BTreeMap::new();
+        let mut tentative: BTreeMap<usize, (Cost<W>, usize, usize)> = BTreeMap::new();
+
+        dv[node.index] = DVValue::SameNode;
+        let mut current = Some((node.index, (Cost::Zero, node.index, node.index)));
+
+        while let Some((index, (cost, first_hop, previous))) = current {
+            settled.insert(index, (cost.clone(), first_hop, previous));
+
+            if index != node.index {
+                let capped = cost.clone().capped(max_metric);
+                let direct = previous == node.index;
+
+                dv[index] = capped.to_dv_value(first_hop, direct);
+                lines.push(if direct {
+                    format!(
+                        "d<sub>{}</sub>({})=C({},{})={}",
+                        node.name,
+                        names.get(&index).unwrap(),
+                        node.name,
+                        names.get(&index).unwrap(),
+                        capped.write_html()
+                    )
+                } else {
+                    let (before, _, _) = settled.get(&previous).unwrap();
+
+                    format!(
+                        "d<sub>{}</sub>({})={}={}={}, first hop {}",
+                        node.name,
+                        names.get(&index).unwrap(),
+                        W::combine_html(&[
+                            format!("d<sub>{}</sub>({})", node.name, names.get(&previous).unwrap()),
+                            format!("C({},{})", names.get(&previous).unwrap(), names.get(&index).unwrap())
+                        ]),
+                        W::combine_html(&[
+                            before.write_html(),
+                            lsdb.two_way_cost(previous, index).map_or(String::from(W::unreachable_html()), |w| w.to_string())
+                        ]),
+                        capped.write_html(),
+                        names.get(&first_hop).unwrap()
+                    )
+                });
+            }
+
+            for next in 0..self.nodes.len() {
+                if settled.contains_key(&next) {
+                    continue;
+                }
+
+                if let Some(w) = lsdb.two_way_cost(index, next) {
+                    let through = cost.clone() + Cost::Value(w.to_owned());
+                    let first_hop = if index == node.index { next } else { first_hop };
+
+                    if tentative.get(&next).is_none_or(|(c, _, _)| through < *c) {
+                        tentative.insert(next, (through, first_hop, index));
+                    }
+                }
+            }
+
+            // The closest node goes next, the lowest one on ties
+            current = tentative
+                .iter()
+                .min_by(|(a, (cost_a, _, _)), (b, (cost_b, _, _))| cost_a.cmp(cost_b).then(a.cmp(b)))
+                .map(|(index, entry)| (*index, entry.clone()));
+
+            if let Some((index, _)) = &current {
+                tentative.remove(index);
+            }
+        }
+
+        for (index, name) in names {
+            if !settled.contains_key(index) {
+                lines.push(format!("d<sub>{}</sub>({})={}", node.name, name, W::unreachable_html()));
+            }
+        }
+
+        (dv, lines)
+    }
+
+    /// Prints a node for the link state protocol: its routes, then every LSA in its
+    /// database with the cost of each link. `fresh` has the (node, origin) pairs that
+    /// were just installed.
+    pub(super) fn print_link_state_node<Writer: Write>(
+        &self,
+        writer: &mut Writer,
+        node: &Node<W>,
+        computed: Option<&(Vec<DVValue<W>>, Vec<String>)>,
+        fresh: &HashSet<(usize, usize)>,
+        removed: &[(usize, usize)],
+        loops: &[ForwardingLoop]
+    ) -> Result<(), Box<dyn Error>> {
+        let names = self.node_names();
+        self.print_routes(writer, node, computed.map(|(dv, _)| dv), &node.timers, loops)?;
+
+        for (origin, lsa) in &node.lsdb.lsas {
+            if fresh.contains(&(node.index, *origin)) {
+                writeln!(writer, "\t<tr class=\"fresh\">")?;
+            } else {
+                writeln!(writer, "\t<tr>")?;
+            }
+            writeln!(writer, "\t\t<th>LSA {} #{}</th>", names.get(origin).unwrap(), lsa.sequence)?;
+
+            for index in 0..self.nodes.len() {
+                match lsa.links.iter().find(|(n, _)| *n == index) {
+                    Some((_, w)) => writeln!(writer, "\t\t<td>{}</td>", w)?,
+                    None => writeln!(writer, "\t\t<td></td>")?
+                }
+            }
+
+            writeln!(writer, "\t</tr>")?;
+        }
+
+        self.close_table(writer, node, removed)?;
+
+        if let Some((_, lines)) = computed {
+            writeln!(writer, "<div class=\"details\">")?;
+            for line in lines {
+                writeln!(writer, "\t<div>{}</div>", line)?;
+            }
+            writeln!(writer, "</div>")?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a round of the link state protocol. Nodes whose links changed originate a new
+    /// LSA, every node sends what's in its outbox to its neighbors, and the ones that learned
+    /// something run SPF again. Newly installed LSAs are flooded on in the next round,
+    /// and a new neighbor gets the whole database.
+    ///
+    /// Flooding is acknowledged in OSPF, so LSAs aren't lost on unreliable links.
+    pub(super) fn run_link_state(&self, html_factory: &mut HtmlFiles) -> Result<NewState<W>, Box<dyn Error>> {
+        let names = self.node_names();
+        let mut world = self.clone();
+        let mut trace: Vec<String> = Vec::new();
+        let mut changed: BTreeSet<usize> = BTreeSet::new();
+        let mut fresh: HashSet<(usize, usize)> = HashSet::new();
+
+        world.generation += 1;
+
+        // A node notices changes to its own links right away
+        for node in self.nodes.iter().filter(|n| !n.is_down()) {
+            let links: Vec<(usize, W)> = node.neighbors.iter().map(|n| (n.index, n.direct_cost.to_owned())).collect();
+            let previous = node.lsdb.lsas.get(&node.index);
+
+            if previous.is_some_and(|lsa| lsa.links == links) {
+                continue;
+            }
+
+            let sequence = previous.map_or(1, |lsa| lsa.sequence + 1);
+            let lsdb = &mut world.nodes[node.index].lsdb;
+            let known: Vec<usize> = lsdb.lsas.keys().copied().collect();
+
+            for (neighbor, _) in &links {
+                let outbox = lsdb.outbox.entry(*neighbor).or_default();
+
+                if previous.is_none_or(|lsa| !lsa.links.iter().any(|(n, _)| n == neighbor)) {
+                    outbox.extend(known.iter().copied());
+                }
+                outbox.insert(node.index);
+            }
+
+            lsdb.lsas.insert(node.index, Lsa { sequence, links });
+            trace.push(format!("{} originated LSA #{}", node.name, sequence));
+            changed.insert(node.index);
+            fresh.insert((node.index, node.index));
+        }
+
+        // (from, to, origin, lsa)
+        let mut deliveries: Vec<(usize, usize, usize, Lsa<W>)> = Vec::new();
+
+        for node in world.nodes.iter_mut().filter(|n| !n.is_down() && !n.silent) {
+            for (neighbor, origins) in std::mem::take(&mut node.lsdb.outbox) {
+                // The link may be gone since the LSAs were queued
+                if node.neighbor(neighbor).is_none() || self.nodes[neighbor].is_down() {
+                    continue;
+                }
+
+                let mut sent = Vec::new();
+                for origin in origins {
+                    let lsa = node.lsdb.lsas.get(&origin).unwrap().clone();
+
+                    sent.push(format!("{} #{}", names.get(&origin).unwrap(), lsa.sequence));
+                    deliveries.push((node.index, neighbor, origin, lsa));
+                }
+
+                trace.push(format!(
+                    "{}&#8594;{}: LSA {}",
+                    node.name,
+                    names.get(&neighbor).unwrap(),
+                    sent.join(", ")
+                ));
+            }
+        }
+
+        world.messages += deliveries.len() as u32;
+
+        for (from, to, origin, lsa) in deliveries {
+            let neighbors: Vec<usize> = world.nodes[to].neighbors.iter().map(|n| n.index).collect();
+            let lsdb = &mut world.nodes[to].lsdb;
+            let installed = lsdb.lsas.get(&origin).cloned();
+
+            if origin == to {
+                // A copy of its own LSA from before a restart, the node takes over with a higher number
+                let own = installed.unwrap();
+
+                if lsa.sequence > own.sequence || (lsa.sequence == own.sequence && lsa.links != own.links) {
+                    lsdb.lsas.insert(to, Lsa { sequence: lsa.sequence + 1, links: own.links });
+
+                    for neighbor in neighbors {
+                        lsdb.outbox.entry(neighbor).or_default().insert(to);
+                    }
+
+                    trace.push(format!(
+                        "{} got its old LSA #{}, goes on from #{} with #{}",
+                        names.get(&to).unwrap(),
+                        lsa.sequence,
+                        own.sequence,
+                        lsa.sequence + 1
+                    ));
+                }
+
+                continue;
+            }
+
+            if installed.is_none_or(|l| lsa.sequence > l.sequence) {
+                lsdb.lsas.insert(origin, lsa);
+
+                for neighbor in neighbors.into_iter().filter(|n| *n != from) {
+                    lsdb.outbox.entry(neighbor).or_default().insert(origin);
+                }
+
+                changed.insert(to);
+                fresh.insert((to, origin));
+            }
+        }
+
+        let mut computed = BTreeMap::new();
+        for index in changed.iter().filter(|n| !world.nodes[**n].is_down()) {
+            computed.insert(*index, world.shortest_path_first(&world.nodes[*index], &names));
+        }
+
+        let mut new_dvs = world.copy_dvs();
+        for (index, (dv, _)) in &computed {
+            new_dvs.insert(*index, dv.clone());
+        }
+
+        let loops = world.forwarding_loops(&new_dvs);
+
+        let mut writer: Vec<u8> = Vec::new();
+        if !trace.is_empty() {
+            writeln!(writer, "<div class=\"events\">")?;
+            for line in &trace {
+                writeln!(writer, "\t<div>{}</div>", line)?;
+            }
+            writeln!(writer, "</div>")?;
+        }
+
+        for node in &world.nodes {
+            world.print_link_state_node(&mut writer, node, computed.get(&node.index), &fresh, &[], &loops)?;
+        }
+
+        let warnings: Vec<String> = loops.iter().map(|l| l.describe(&names)).collect();
+        html_factory.create(|w| {
+            writeln!(w, "<h2>t={}</h2>", world.generation)?;
+            w.write_all(warning_banner(&warnings).as_bytes())?;
+            w.write_all(writer.as_slice())?;
+            Ok(())
+        })?;
+
+        if trace.is_empty() {
+            return Ok(NewState::NotChanged);
+        }
+
+        for (index, (dv, _)) in computed {
+            world.nodes[index].dv = dv;
+        }
+
+        for node in world.nodes.iter_mut() {
+            node.has_updates = false;
+        }
+
+        if !loops.is_empty() {
+            world.loop_counts.push((world.generation, loops.len()));
+        }
+
+        let generation = world.generation;
+        world.move_packets(generation, &Delays::new());
+
+        Ok(NewState::Changed(Box::new(world)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lsa(links: Vec<(usize, u32)>) -> Lsa<u32> {
+        Lsa { sequence: 1, links }
+    }
+
+    #[test]
+    fn shortest_path_first_ignores_one_way_links() {
+        let mut world: World<u32> = World::new(vec!("A", "B", "C", "D"));
+        let names = world.node_names();
+
+        // B advertises D, but D doesn't advertise B back
+        world.nodes[0].lsdb.lsas = BTreeMap::from([
+            (0, lsa(vec!((1, 1), (2, 5)))),
+            (1, lsa(vec!((0, 1), (2, 1), (3, 1)))),
+            (2, lsa(vec!((0, 5), (1, 1)))),
+            (3, lsa(Vec::new()))
+        ]);
+
+        let (dv, _) = world.shortest_path_first(&world.nodes[0], &names);
+
+        assert!(matches!(dv[0], DVValue::SameNode));
+        assert!(matches!(dv[1], DVValue::DirectDistance(1)));
+        assert!(matches!(dv[2], DVValue::Distance(2, 1)));
+        assert!(matches!(dv[3], DVValue::Infinity));
+    }
+
+    #[test]
+    fn shortest_path_first_uses_the_weight_of_each_direction() {
+        let mut world: World<u32> = World::new(vec!("A", "B", "C"));
+        let names = world.node_names();
+
+        // A to B is expensive, B to A is cheap
+        let lsas = BTreeMap::from([
+            (0, lsa(vec!((1, 10), (2, 1)))),
+            (1, lsa(vec!((0, 1), (2, 1)))),
+            (2, lsa(vec!((0, 1), (1, 1))))
+        ]);
+        world.nodes[0].lsdb.lsas = lsas.clone();
+        world.nodes[1].lsdb.lsas = lsas;
+
+        let (from_a, _) = world.shortest_path_first(&world.nodes[0], &names);
+        let (from_b, _) = world.shortest_path_first(&world.nodes[1], &names);
+
+        assert!(matches!(from_a[1], DVValue::Distance(2, 2)));
+        assert!(matches!(from_b[0], DVValue::DirectDistance(1)));
+    }
+}
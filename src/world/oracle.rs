@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cost::Cost;
+use crate::metric::Metric;
+use super::World;
+
+/// How a converged entry differs from the shortest paths.
+#[derive(Debug, Clone)]
+pub enum WrongRoute<W: Metric> {
+    /// The entry's cost isn't the shortest path's
+    Cost(Cost<W>, Cost<W>),
+    /// The cost is right but the next hop isn't on any shortest path
+    NextHop(usize),
+}
+
+/// A converged table entry that doesn't match the shortest paths.
+#[derive(Debug, Clone)]
+pub struct WrongEntry<W: Metric> {
+    node: usize,
+    destination: usize,
+    wrong: WrongRoute<W>
+}
+
+impl<W: Metric> WrongEntry<W> {
+    pub fn describe(&self, names: &BTreeMap<usize, String>) -> String {
+        let node = names.get(&self.node).unwrap();
+        let destination = names.get(&self.destination).unwrap();
+
+        match &self.wrong {
+            WrongRoute::Cost(found, expected) => format!(
+                "{} reaches {} at {}, the shortest path costs {}",
+                node,
+                destination,
+                found.write_html().replace("&infin;", "infinity"),
+                expected.write_html().replace("&infin;", "infinity")
+            ),
+            WrongRoute::NextHop(via) => format!(
+                "{} sends packets for {} through {}, which isn't on a shortest path",
+                node,
+                destination,
+                names.get(via).unwrap()
+            )
+        }
+    }
+}
+
+/// The result of comparing the tables of a converged run with the shortest paths.
+#[derive(Debug, Clone)]
+pub struct RouteCheck<W: Metric> {
+    time: u32,
+    // A silent node's neighbors keep its old table, there's nothing to compare with
+    skipped: Option<usize>,
+    wrong: Vec<WrongEntry<W>>
+}
+
+/// The cost of the shortest path from every node to `destination`, following the
+/// directed `relations`. Nodes that can't reach it are left out.
+fn shortest_paths<W: Metric>(
+    relations: &HashMap<(usize, usize), W>,
+    destination: usize
+) -> HashMap<usize, Cost<W>> {
+    let mut distances: HashMap<usize, Cost<W>> = HashMap::new();
+    let mut tentative: HashMap<usize, Cost<W>> = HashMap::new();
+    tentative.insert(destination, Cost::Zero);
+
+    // The graphs are small, picking the closest node by scanning is plenty
+    while let Some((node, cost)) = tentative
+        .iter()
+        .min_by(|(a, cost_a), (b, cost_b)| cost_a.cmp(cost_b).then(a.cmp(b)))
+        .map(|(node, cost)| (*node, cost.clone())) {
+        tentative.remove(&node);
+
+        // Relations are (sender, receiver), so the paths towards `destination` are followed backwards
+        for ((from, to), weight) in relations {
+            if *to != node || distances.contains_key(from) || *from == node {
+                continue;
+            }
+
+            let through = cost.clone() + Cost::Value(weight.to_owned());
+            if tentative.get(from).is_none_or(|c| through < *c) {
+                tentative.insert(*from, through);
+            }
+        }
+
+        distances.insert(node, cost);
+    }
+
+    distances
+}
+
+impl<W: Metric> World<W> {
+    /// Compares every table with the shortest paths over the current links, and keeps the
+    /// result. Only meaningful once the run converged.
+    pub(super) fn check_routes(&mut self) {
+        if !self.options.verify {
+            return;
+        }
+
+        let skipped = self.nodes.iter().find(|n| n.silent && !n.is_down()).map(|n| n.index);
+        let mut wrong = Vec::new();
+
+        if skipped.is_none() {
+            let relations = self.copy_relations();
+            let max_metric = self.options.max_metric.as_ref();
+
+            for destination in 0..self.nodes.len() {
+                let distances = shortest_paths(&relations, destination);
+                let distance = |node: usize| distances.get(&node).cloned().unwrap_or(Cost::Infinity);
+
+                for node in self.nodes.iter().filter(|n| !n.is_down()) {
+                    let entry = &node.dv[destination];
+                    let found: Cost<W> = entry.into();
+                    let expected = distance(node.index).capped(max_metric);
+
+                    if found != expected {
+                        wrong.push(WrongEntry {
+                            node: node.index,
+                            destination,
+                            wrong: WrongRoute::Cost(found, expected)
+                        });
+                        continue;
+                    }
+
+                    let on_shortest_path = |via: usize| relations
+                        .get(&(node.index, via))
+                        .is_some_and(|w| Cost::Value(w.to_owned()) + distance(via) == distance(node.index));
+
+                    for via in entry.next_hops(destination).into_iter().filter(|via| !on_shortest_path(*via)) {
+                        wrong.push(WrongEntry { node: node.index, destination, wrong: WrongRoute::NextHop(via) });
+                    }
+                }
+            }
+        }
+
+        self.route_checks.push(RouteCheck { time: self.generation, skipped, wrong });
+    }
+
+    /// What the checks against the shortest paths found. A single line when every
+    /// converged run had the right tables.
+    pub fn route_check_report(&self) -> Vec<String> {
+        let names = self.node_names();
+        let mut report = Vec::new();
+
+        if self.route_checks.is_empty() {
+            return report;
+        }
+
+        for check in &self.route_checks {
+            if let Some(silent) = check.skipped {
+                report.push(format!(
+                    "routes at t={} not checked against the shortest paths, {} is silent",
+                    check.time,
+                    names.get(&silent).unwrap()
+                ));
+            }
+
+            for entry in &check.wrong {
+                report.push(format!("wrong route at t={}: {}", check.time, entry.describe(&names)));
+            }
+        }
+
+        if report.is_empty() {
+            let times: Vec<String> = self.route_checks.iter().map(|c| format!("t={}", c.time)).collect();
+            report.push(format!("routes match the shortest paths at {}", times.join(", ")));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_paths_follow_directed_weights() {
+        // 1 reaches 0 cheaper through 2, the way back from 0 is direct
+        let relations: HashMap<(usize, usize), u32> = HashMap::from([
+            ((0, 1), 1),
+            ((1, 0), 5),
+            ((1, 2), 1),
+            ((2, 1), 10),
+            ((2, 0), 1),
+            ((0, 2), 10),
+            // 3 only hears from 0, it can't send anything back
+            ((0, 3), 1)
+        ]);
+
+        let to_0 = shortest_paths(&relations, 0);
+        assert_eq!(to_0.get(&0), Some(&Cost::Zero));
+        assert_eq!(to_0.get(&1), Some(&Cost::Value(2)));
+        assert_eq!(to_0.get(&2), Some(&Cost::Value(1)));
+        assert_eq!(to_0.get(&3), None);
+
+        let to_1 = shortest_paths(&relations, 1);
+        assert_eq!(to_1.get(&0), Some(&Cost::Value(1)));
+        assert_eq!(to_1.get(&2), Some(&Cost::Value(2)));
+
+        let to_3 = shortest_paths(&relations, 3);
+        assert_eq!(to_3.get(&1), Some(&Cost::Value(3)));
+        assert_eq!(to_3.get(&2), Some(&Cost::Value(2)));
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::cost::Cost;
+use crate::metric::Metric;
+use super::events::{Delays, DEFAULT_DELAY};
+use super::World;
+
+/// Gives up on a packet after this many hops, routes can change under it forever.
+const PACKET_TTL: usize = 64;
+
+/// Where a packet ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEnd {
+    Delivered,
+    /// The node had no route to the destination
+    BlackHole(usize),
+    /// Following the same tables came back to this node
+    Loop(usize),
+    /// The node the packet was sent to is down
+    NodeDown(usize),
+    /// Ran out of hops
+    Expired,
+}
+
+/// A packet's way through the network.
+#[derive(Debug, Clone)]
+pub struct Trace<W: Metric> {
+    // The nodes it went through, starting with the source
+    hops: Vec<usize>,
+    // The link weights it paid on the way
+    cost: Cost<W>,
+    end: TraceEnd
+}
+
+impl<W: Metric> Trace<W> {
+    pub fn describe(&self, names: &BTreeMap<usize, String>) -> String {
+        let hops: Vec<&str> = self.hops.iter().map(|n| names.get(n).unwrap().as_str()).collect();
+
+        format!(
+            "{}, cost {}, {}",
+            hops.join(" -> "),
+            self.cost.write_html().replace("&infin;", "infinity"),
+            describe_end(&self.end, names)
+        )
+    }
+}
+
+fn describe_end(end: &TraceEnd, names: &BTreeMap<usize, String>) -> String {
+    match end {
+        TraceEnd::Delivered => String::from("delivered"),
+        TraceEnd::BlackHole(node) => format!("black hole at {}", names.get(node).unwrap()),
+        TraceEnd::Loop(node) => format!("loop back to {}", names.get(node).unwrap()),
+        TraceEnd::NodeDown(node) => format!("{} is down", names.get(node).unwrap()),
+        TraceEnd::Expired => format!("gave up after {} hops", PACKET_TTL)
+    }
+}
+
+/// A packet that moves a hop at a time while the simulation runs, so it sees the
+/// routes change under it.
+#[derive(Debug, Clone)]
+pub struct Packet<W: Metric> {
+    from: usize,
+    to: usize,
+    injected: u32,
+    // (node, time it got there)
+    hops: Vec<(usize, u32)>,
+    cost: Cost<W>,
+    end: Option<TraceEnd>,
+    // What a traceroute said when the packet was sent, a trace for every branch
+    expected: Vec<Trace<W>>
+}
+
+impl<W: Metric> Packet<W> {
+    /// Picks the next hop out of several equal-cost ones. Like a router hashing the
+    /// addresses, every packet between the same two nodes takes the same one.
+    fn pick<T: Clone>(&self, hops: &[T]) -> T {
+        hops[(self.from + self.to) % hops.len()].clone()
+    }
+}
+
+/// Where forwarding a packet at a node goes next, every next hop with the weight of its link.
+enum Forward<W> {
+    Arrived,
+    Next(Vec<(usize, W)>),
+    Stop(TraceEnd)
+}
+
+impl<W: Metric> World<W> {
+    fn forward(&self, node: usize, to: usize) -> Forward<W> {
+        if node == to {
+            return Forward::Arrived;
+        }
+
+        if self.nodes[node].is_down() {
+            return Forward::Stop(TraceEnd::NodeDown(node));
+        }
+
+        let next: Vec<(usize, W)> = self.nodes[node].dv[to]
+            .next_hops(to)
+            .into_iter()
+            .filter_map(|next| self.nodes[node].neighbor(next))
+            .map(|neighbor| (neighbor.index, neighbor.direct_cost.to_owned()))
+            .collect();
+
+        if next.is_empty() {
+            Forward::Stop(TraceEnd::BlackHole(node))
+        } else {
+            Forward::Next(next)
+        }
+    }
+
+    /// Follows the next hops from the end of `hops` to `to`, adding a trace for every
+    /// branch to `traces`.
+    fn trace_branches(&self, hops: Vec<usize>, cost: Cost<W>, to: usize, traces: &mut Vec<Trace<W>>) {
+        let node = *hops.last().unwrap();
+
+        match self.forward(node, to) {
+            Forward::Arrived => traces.push(Trace { hops, cost, end: TraceEnd::Delivered }),
+            Forward::Stop(end) => traces.push(Trace { hops, cost, end }),
+            Forward::Next(next) => for (next, weight) in next {
+                let mut branch = hops.clone();
+                let looped = branch.contains(&next);
+                let cost = cost.clone() + Cost::Value(weight);
+
+                branch.push(next);
+
+                if looped {
+                    traces.push(Trace { hops: branch, cost, end: TraceEnd::Loop(next) });
+                } else {
+                    self.trace_branches(branch, cost, to, traces);
+                }
+            }
+        }
+    }
+
+    fn trace_indices(&self, from: usize, to: usize) -> Vec<Trace<W>> {
+        let mut traces = Vec::new();
+        self.trace_branches(vec!(from), Cost::Zero, to, &mut traces);
+
+        traces
+    }
+
+    /// The way `packet` takes from `from` on, the same branch every time.
+    fn trace_packet(&self, packet: &Packet<W>, from: usize) -> Trace<W> {
+        let mut hops = vec!(from);
+        let mut cost = Cost::Zero;
+        let mut node = from;
+
+        let end = loop {
+            match self.forward(node, packet.to) {
+                Forward::Arrived => break TraceEnd::Delivered,
+                Forward::Stop(end) => break end,
+                Forward::Next(next) => {
+                    let (next, weight) = packet.pick(&next);
+                    let looped = hops.contains(&next);
+
+                    hops.push(next);
+                    cost = cost + Cost::Value(weight);
+                    node = next;
+
+                    if looped {
+                        break TraceEnd::Loop(next);
+                    }
+                }
+            }
+        };
+
+        Trace { hops, cost, end }
+    }
+
+    /// Follows the next hops in the current tables from `from` to `to`, like traceroute.
+    /// Multipath routes give a trace for every branch.
+    pub fn trace(&self, from: &str, to: &str) -> Result<Vec<Trace<W>>, Box<dyn Error>> {
+        Ok(self.trace_indices(self.node_index(from)?, self.node_index(to)?))
+    }
+
+    /// Sends a packet from `from` to `to` once the simulation reaches `at`.
+    pub fn inject(&mut self, from: &str, to: &str, at: u32) -> Result<(), Box<dyn Error>> {
+        self.packets.push(Packet {
+            from: self.node_index(from)?,
+            to: self.node_index(to)?,
+            injected: at,
+            hops: Vec::new(),
+            cost: Cost::Zero,
+            end: None,
+            expected: Vec::new()
+        });
+
+        Ok(())
+    }
+
+    /// Moves every packet that's due by `time` using the current tables. A hop takes the
+    /// delay of its link, which is a generation on the lockstep engine.
+    pub(super) fn move_packets(&mut self, time: u32, delays: &Delays) {
+        let mut packets = std::mem::take(&mut self.packets);
+
+        for packet in packets.iter_mut().filter(|p| p.end.is_none() && p.injected <= time) {
+            if packet.hops.is_empty() {
+                packet.hops.push((packet.from, time));
+                packet.expected = self.trace_indices(packet.from, packet.to);
+            }
+
+            while let (None, Some((node, arrived))) = (packet.end, packet.hops.last().copied()) {
+                if arrived > time {
+                    break;
+                }
+
+                if packet.hops.len() > PACKET_TTL {
+                    packet.end = Some(TraceEnd::Expired);
+                    break;
+                }
+
+                match self.forward(node, packet.to) {
+                    Forward::Arrived => packet.end = Some(TraceEnd::Delivered),
+                    Forward::Stop(end) => packet.end = Some(end),
+                    Forward::Next(next) => {
+                        let (next, weight) = packet.pick(&next);
+                        let delay = delays.get(&(node, next)).copied().unwrap_or(DEFAULT_DELAY);
+
+                        packet.hops.push((next, time + delay));
+                        packet.cost = packet.cost.clone() + Cost::Value(weight);
+                    }
+                }
+            }
+        }
+
+        self.packets = packets;
+    }
+
+    /// Delivers the packets still on the way through the final tables. They don't change
+    /// anymore, so coming back to a node is a loop.
+    pub fn finish_packets(&mut self) {
+        let mut packets = std::mem::take(&mut self.packets);
+
+        for packet in packets.iter_mut().filter(|p| p.end.is_none()) {
+            if packet.hops.is_empty() {
+                // Sent after the simulation settled, the final tables are what it would have seen
+                packet.injected = packet.injected.min(self.generation);
+                packet.hops.push((packet.from, self.generation));
+                packet.expected = self.trace_indices(packet.from, packet.to);
+            }
+
+            let (node, arrived) = *packet.hops.last().unwrap();
+            let rest = self.trace_packet(packet, node);
+            let time = arrived.max(self.generation);
+
+            for (offset, next) in rest.hops.iter().skip(1).enumerate() {
+                packet.hops.push((*next, time + offset as u32 + 1));
+            }
+
+            packet.cost = packet.cost.clone() + rest.cost;
+            packet.end = Some(rest.end);
+        }
+
+        self.packets = packets;
+    }
+
+    /// What happened to every packet: the traceroute when it was sent, then the way
+    /// it really went.
+    pub fn packet_report(&self) -> Vec<String> {
+        let names = self.node_names();
+        let mut report = Vec::new();
+
+        for packet in &self.packets {
+            let title = format!(
+                "packet {} -> {} sent at t={}",
+                names.get(&packet.from).unwrap(),
+                names.get(&packet.to).unwrap(),
+                packet.injected
+            );
+
+            for expected in &packet.expected {
+                report.push(format!("{}: traceroute {}", title, expected.describe(&names)));
+            }
+
+            let hops: Vec<String> = packet.hops
+                .iter()
+                .map(|(node, time)| format!("{} (t={})", names.get(node).unwrap(), time))
+                .collect();
+
+            report.push(format!(
+                "{}: went {}, cost {}, {}",
+                title,
+                hops.join(" -> "),
+                packet.cost.write_html().replace("&infin;", "infinity"),
+                packet.end.map_or(String::from("still on the way"), |end| describe_end(&end, &names))
+            ));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::tests::run_scenario;
+
+    const TIR1: &str = r#"
+        nodes = ["A", "B", "C", "D"]
+        links = [
+            { a = "A", b = "B", weight = 2 },
+            { a = "A", b = "D", weight = 10 },
+            { a = "B", b = "C", weight = 1 },
+            { a = "B", b = "D", weight = 20 },
+            { a = "C", b = "D", weight = 2 },
+        ]
+
+        [[packets]]
+        from = "A"
+        to = "D"
+        at = 5
+
+        [[steps]]
+        operations = [{ op = "change_weight", a = "C", b = "D", weight = 80 }]
+    "#;
+
+    #[test]
+    fn traces_follow_the_settled_tables() {
+        let (world, _) = run_scenario("trace", TIR1);
+        let names = world.node_names();
+        let traces: Vec<String> = world.trace("A", "D").unwrap().iter().map(|t| t.describe(&names)).collect();
+
+        assert_eq!(traces, vec!("A -> D, cost 10, delivered"));
+        assert!(world.trace("A", "X").is_err());
+    }
+
+    #[test]
+    fn packets_sent_while_counting_go_round_the_loop() {
+        let (world, _) = run_scenario("packets", TIR1);
+
+        // The traceroute at t=5 sees the loop, the packet itself gets out once the tables change
+        assert_eq!(world.packet_report(), vec!(
+            "packet A -> D sent at t=5: traceroute A -> B -> C -> B, cost 4, loop back to B",
+            "packet A -> D sent at t=5: went A (t=5) -> B (t=6) -> C (t=7) -> B (t=8) -> C (t=9) -> B (t=10) -> A (t=11) -> D (t=12), cost 18, delivered"
+        ));
+    }
+}